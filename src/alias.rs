@@ -1,28 +1,37 @@
 use crate::contractors::Contractor;
 use crate::errors::CliError;
 use crate::generics::{
-    add_subject, delete_subject, update_subject, view_filtered_set, view_subject, Crud, Filter,
-    Result, View,
+    add_subject, commit_staged, confirm, delete_subject, pick, reconcile, retrieve_or_pick,
+    update_subject, view_filtered_set, view_subject, Crud, Filter, Result, Staged, View,
 };
-use crate::utils::{partition_directive, slugify};
+use crate::hours::HourLog;
+use crate::money::{Currency, Money};
+use crate::output::{self, Columns};
+use crate::utils::{
+    check_slug, interactive, missing_flag, parse_date, partition_directive, slugify, unknown_key,
+};
+use chrono::{Local, NaiveDate};
 use colored::*;
 use read_input::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str::FromStr;
 use structopt::StructOpt;
+use toml::value::{Table, Value};
 use toml::{from_str as from_toml, to_string as to_toml};
 
 enum AliasError {
     InvalidFilterField(String),
+    InvalidSort(String),
 }
 
 impl From<AliasError> for CliError {
     fn from(err: AliasError) -> Self {
         match err {
             AliasError::InvalidFilterField(f) => {
-                Self::CmdError(format!("cannot filter on {}", f.yellow().bold()))
+                unknown_key("cannot filter on", &f, &FILTER_FIELDS)
             }
+            AliasError::InvalidSort(s) => unknown_key("cannot sort on", &s, &SORT_KEYS),
         }
     }
 }
@@ -32,17 +41,57 @@ pub struct Alias {
     pub slug: String,
     pub contractor: String,
     pub short_description: String,
-    pub hourly_rate: u8,
+    pub hourly_rate: Money,
+    /// Scheduled rate changes, ordered by the date they take effect
+    #[serde(default)]
+    pub rate_changes: Vec<RateChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateChange {
+    pub effective_from: NaiveDate,
+    pub rate: Money,
+}
+
+/// Alias details given as flags; prompts are only shown for the ones left out.
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct Fields {
+    /// Slug of the contractor the work is for
+    #[structopt(long = "contractor")]
+    contractor: Option<String>,
+    /// Brief description
+    #[structopt(long = "description")]
+    description: Option<String>,
+    /// Hourly rate (e.g. "90" | "87.50 USD"), in the contractor currency by default; on
+    /// update the new rate applies from today (`alias rate add` takes other dates)
+    #[structopt(long = "rate")]
+    rate: Option<String>,
+}
+
+impl Fields {
+    fn is_empty(&self) -> bool {
+        self.contractor.is_none() && self.description.is_none() && self.rate.is_none()
+    }
 }
 
 #[derive(StructOpt, Debug)]
 pub enum Cmd {
-    /// Create a new alias interactively
+    /// Create a new alias, prompting for details not given as flags
     #[structopt(name = "add")]
-    Create,
-    /// Update an existing alias interactively
+    Create {
+        /// Alias reference (lowercase and no spaces)
+        #[structopt(long = "slug")]
+        slug: Option<String>,
+        #[structopt(flatten)]
+        fields: Fields,
+    },
+    /// Update the given details of an alias, or all of them interactively
     #[structopt(name = "update")]
-    Update { alias: Alias },
+    Update {
+        alias: String,
+        #[structopt(flatten)]
+        fields: Fields,
+    },
     /// View a collection of aliases
     #[structopt(name = "show")]
     Show {
@@ -53,22 +102,90 @@ pub enum Cmd {
     },
     /// View detailed alias stats
     #[structopt(name = "detail")]
-    Detail { alias: Alias },
+    Detail { alias: String },
     /// Delete an alias
     #[structopt(name = "delete")]
-    Delete { alias: Alias },
+    Delete { alias: String },
+    /// Change the slug of an alias and of the hour logs booked on it
+    #[structopt(name = "rename")]
+    Rename { alias: String, new: String },
+    /// Move the hour logs of an alias to another alias and delete it
+    #[structopt(name = "merge")]
+    Merge {
+        from: String,
+        into: String,
+        /// Keep the details of <into> and merge without asking
+        #[structopt(short = "y", long = "yes")]
+        yes: bool,
+    },
+    /// Manage the rate history of an alias
+    #[structopt(name = "rate")]
+    Rate(RateCmd),
+}
+
+#[derive(StructOpt, Debug)]
+pub enum RateCmd {
+    /// Schedule a rate change for an alias
+    #[structopt(name = "add")]
+    Add {
+        alias: String,
+        /// New hourly rate (e.g. "95" | "95.50 EUR"); defaults to the currency of the current rate
+        rate: String,
+        /// Date from which the rate applies (e.g. "YYYY-MM-DD" | <weekday>)
+        #[structopt(short = "d", long = "from", default_value = "today", parse(try_from_str = parse_date))]
+        from: NaiveDate,
+    },
+    /// View the rate history of an alias
+    #[structopt(name = "show")]
+    Show { alias: String },
+}
+
+impl RateCmd {
+    pub fn exec(&self) -> Result<()> {
+        match self {
+            Self::Add { alias, rate, from } => {
+                let mut alias = Alias::retrieve_exact(alias)?;
+                let rate = Money::parse(rate, &alias.current_rate().currency)?;
+                alias.schedule_rate(*from, rate);
+                alias.overwrite()?
+            }
+            Self::Show { alias } => println!("{}", Alias::retrieve(alias)?.format_rates()),
+        };
+        Ok(())
+    }
 }
 
 impl Cmd {
     pub fn exec(&self) -> Result<()> {
         match self {
-            Self::Create => add_subject(Alias::new()?)?,
-            Self::Delete { alias } => delete_subject::<Alias>(&alias.slug)?,
-            Self::Update { alias } => update_subject::<Alias>(&alias.slug)?,
-            Self::Detail { alias } => view_subject::<Alias>(Some(alias.slug.clone()))?,
+            Self::Create { slug, fields } => {
+                let alias = Alias::new(slug.clone(), fields)?;
+                alias.validate()?;
+                add_subject(alias)?
+            }
+            Self::Delete { alias } => delete_subject::<Alias>(alias)?,
+            Self::Update { alias, fields } if fields.is_empty() => {
+                if !interactive() {
+                    return Err(CliError::CmdError(
+                        "nothing to update, give the new details as flags".into(),
+                    ));
+                }
+                update_subject::<Alias>(alias)?
+            }
+            Self::Update { alias, fields } => {
+                let alias = Alias::retrieve_exact(alias)?.update(fields)?;
+                alias.validate()?;
+                alias.overwrite()?
+            }
+            Self::Detail { alias } => view_subject::<Alias>(Some(alias.clone()))?,
             Self::Show { filters, sort } => {
                 view_filtered_set::<Alias, F, S>(filters.to_vec(), sort.clone())?
             }
+            Self::Rate(cmd) => cmd.exec()?,
+            Self::Rename { alias, new } => Alias::retrieve_exact(alias)?.rename(new)?,
+            Self::Merge { from, into, yes } => {
+                Alias::retrieve_exact(from)?.merge(Alias::retrieve_exact(into)?, *yes)?
+            }
         };
         Ok(())
     }
@@ -76,19 +193,27 @@ impl Cmd {
 
 impl Crud for Alias {
     const FILE: &'static str = "alias_test.toml";
+    const RESOLVE_PREFIX: bool = true;
 
     fn identifier(&self) -> String {
         self.slug.to_owned()
     }
 
     fn deserialize(tomlstr: String) -> Result<HashMap<String, Alias>> {
-        Ok(from_toml(&tomlstr)?)
+        let mut table = from_toml::<Table>(&tomlstr)?;
+        migrate_legacy_rates(&mut table)?;
+        Ok(Value::Table(table).try_into()?)
     }
 
     fn serialize(map: HashMap<String, Alias>) -> Result<String> {
         Ok(to_toml(&map)?)
     }
 
+    fn validate(&self) -> Result<()> {
+        check_slug(&self.slug)?;
+        Contractor::write_ok(&self.contractor, true)
+    }
+
     fn interactive_update(&self) -> Self {
         let slug = self.slug.to_owned();
         let contractor = input::<String>()
@@ -99,25 +224,80 @@ impl Crud for Alias {
             .msg(format!("Brief description: [{}]", self.short_description))
             .default(self.short_description.clone())
             .get();
-        let hourly_rate = input::<u8>()
-            .msg(format!("Hourly rate: [{}]", self.hourly_rate))
-            .default(self.hourly_rate)
-            .get();
-        Self {
+        let current = self.current_rate().clone();
+        let rate = prompt_rate(
+            format!("Hourly rate from today: [{}]", current),
+            &current.currency,
+            Some(current.clone()),
+        );
+        let mut alias = Self {
             slug,
             contractor,
             short_description,
-            hourly_rate,
+            ..self.clone()
+        };
+        if rate != current {
+            alias.schedule_rate(today(), rate);
         }
+        alias
     }
 }
 
-impl FromStr for Alias {
-    type Err = CliError;
+/// Rates used to be whole numbers without a currency; they are read in the currency of the
+/// contractor of the alias and written with it on the next change.
+fn migrate_legacy_rates(table: &mut Table) -> Result<()> {
+    let legacy = |alias: &Value| matches!(alias.get("hourly_rate"), Some(Value::Integer(_)));
+    if !table.values().any(legacy) {
+        return Ok(());
+    }
+    let contractors = Contractor::mapping()?;
+    for alias in table
+        .iter_mut()
+        .filter_map(|(_, alias)| alias.as_table_mut())
+    {
+        let amount = match alias.get("hourly_rate") {
+            Some(Value::Integer(amount)) => *amount,
+            _ => continue,
+        };
+        let currency = alias
+            .get("contractor")
+            .and_then(Value::as_str)
+            .and_then(|slug| contractors.get(slug))
+            .map_or_else(Currency::default, |contractor| contractor.currency.clone());
+        let rate = Money::whole(amount, currency).to_string();
+        alias.insert("hourly_rate".into(), Value::String(rate));
+    }
+    Ok(())
+}
 
-    fn from_str(s: &str) -> Result<Self> {
-        Ok(Self::retrieve(s)?)
+/// The hour logs with those of alias `old` booked on `new` instead, and the ids of the
+/// moved ones. Nothing is written, see `stage_bookings`.
+fn move_bookings(old: &str, new: &str) -> Result<(HashMap<String, HourLog>, Vec<String>)> {
+    let mut logs = HourLog::mapping()?;
+    let mut moved = vec![];
+    for log in logs.values_mut().filter(|log| log.alias == old) {
+        log.alias = new.to_string();
+        moved.push(log.id.clone());
     }
+    Ok((logs, moved))
+}
+
+/// Stages the hour logs from `move_bookings`; nothing is staged when none moved.
+fn stage_bookings(logs: HashMap<String, HourLog>, moved: &[String]) -> Result<Vec<Staged>> {
+    if moved.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(vec![HourLog::stage_map(logs)?])
+}
+
+impl Columns for Alias {
+    const COLUMNS: &'static [&'static str] = &[
+        "slug",
+        "contractor",
+        "short_description",
+        "hourly_rate",
+        "rate_changes",
+    ];
 }
 
 impl View for Alias {
@@ -127,31 +307,227 @@ impl View for Alias {
             self.slug.red().bold(),
             self.contractor.cyan(),
             self.short_description,
-            self.hourly_rate.to_string().green().bold()
+            self.current_rate().to_string().green().bold()
         )
     }
+
+    fn format_detail(&self) -> String {
+        format!("{}\n{}", self.format_list_item(), self.format_rates())
+    }
 }
 
 impl Alias {
-    fn new() -> Result<Self> {
-        let slug = input::<String>()
-            .msg("Alias: ")
-            .add_test(|x| *x == slugify(x.into()))
-            .get();
-        let contractor = input::<String>().msg("Contractor slug: ").get();
-        let contractor = Contractor::from_str(&contractor)?;
-        let contractor = contractor.slug;
-        let short_description = input::<String>().msg("Brief description: ").get();
-        let hourly_rate = input::<u8>().msg("Hourly rate: ").get();
+    fn new(slug: Option<String>, fields: &Fields) -> Result<Self> {
+        let prompt = interactive();
+        let slug = match (slug, prompt) {
+            (Some(slug), _) => slug,
+            (None, true) => input::<String>()
+                .msg("Alias: ")
+                .add_test(|x| *x == slugify(x.into()))
+                .get(),
+            (None, false) => return Err(missing_flag("slug")),
+        };
+        let contractor = match (&fields.contractor, prompt) {
+            (Some(contractor), _) => {
+                retrieve_or_pick::<Contractor>(Some(contractor), "Contractor")?
+            }
+            (None, true) => pick::<Contractor>("Contractor", "")?,
+            (None, false) => return Err(missing_flag("contractor")),
+        };
+        let short_description = match (&fields.description, prompt) {
+            (Some(description), _) => description.clone(),
+            (None, true) => input::<String>().msg("Brief description: ").get(),
+            (None, false) => return Err(missing_flag("description")),
+        };
+        let hourly_rate = match (&fields.rate, prompt) {
+            (Some(rate), _) => Money::parse(rate, &contractor.currency)?,
+            (None, true) => prompt_rate(
+                format!("Hourly rate [{}]: ", contractor.currency),
+                &contractor.currency,
+                None,
+            ),
+            (None, false) => return Err(missing_flag("rate")),
+        };
         Ok(Self {
             slug,
-            contractor,
+            contractor: contractor.slug,
             short_description,
             hourly_rate,
+            rate_changes: vec![],
         })
     }
+
+    /// Copy of the alias with the details given in `fields`.
+    fn update(&self, fields: &Fields) -> Result<Self> {
+        let mut alias = self.clone();
+        if let Some(contractor) = &fields.contractor {
+            alias.contractor = contractor.clone();
+        }
+        if let Some(description) = &fields.description {
+            alias.short_description = description.clone();
+        }
+        if let Some(rate) = &fields.rate {
+            let rate = Money::parse(rate, &self.current_rate().currency)?;
+            // bookings made before today keep the rate they were made at
+            if rate != *self.current_rate() {
+                alias.schedule_rate(today(), rate);
+            }
+        }
+        Ok(alias)
+    }
+
+    /// Moves the alias to the slug `new` together with the hour logs booked on it, writing
+    /// both data files or neither.
+    fn rename(mut self, new: &str) -> Result<()> {
+        check_slug(new)?;
+        Self::write_ok(new, false)?;
+        let old = std::mem::replace(&mut self.slug, new.to_string());
+        let (logs, moved) = move_bookings(&old, new)?;
+        let mut staged = stage_bookings(logs, &moved)?;
+        let mut aliases = Self::mapping()?;
+        aliases.remove(&old);
+        aliases.insert(self.slug.clone(), self);
+        staged.push(Self::stage_map(aliases)?);
+        commit_staged(staged)?;
+        output::note(format!(
+            "Renamed {} to {} ({} hour logs updated)",
+            old,
+            new,
+            moved.len()
+        ));
+        Ok(())
+    }
+
+    /// Moves the hour logs of this alias to `into` and deletes it, after a preview and
+    /// confirmation. Differing details are picked interactively unless `yes` is set, in
+    /// which case those of `into` are kept.
+    fn merge(self, into: Alias, yes: bool) -> Result<()> {
+        if self.slug == into.slug {
+            return Err(CliError::CmdError(
+                "cannot merge an alias into itself".into(),
+            ));
+        }
+        if self.contractor != into.contractor {
+            return Err(CliError::CmdError(format!(
+                "{} belongs to {} and {} to {}, aliases of different contractors cannot be merged",
+                self.slug.yellow().bold(),
+                self.contractor,
+                into.slug.yellow().bold(),
+                into.contractor
+            )));
+        }
+        let (logs, moved) = move_bookings(&self.slug, &into.slug)?;
+        output::note(format!(
+            "Merging {} into {}: {} hour logs move to {} and {} is deleted",
+            self.slug.yellow().bold(),
+            into.slug.green().bold(),
+            moved.len(),
+            into.slug,
+            self.slug
+        ));
+        let repriced = moved
+            .iter()
+            .filter(|id| self.rate_on(logs[*id].date) != into.rate_on(logs[*id].date))
+            .count();
+        if repriced > 0 || self.current_rate() != into.current_rate() {
+            output::note(format!(
+                "{} Rates differ: {} is at {} and {} at {} today; with the rates of {}, {} moved \
+                 hour logs are billed at a different rate",
+                "[Merge Warning]".yellow().bold(),
+                self.slug,
+                self.current_rate(),
+                into.slug,
+                into.current_rate(),
+                into.slug,
+                repriced
+            ));
+        }
+        let target = into.slug.clone();
+        let merged = reconcile(&self, into, !yes && interactive())?;
+        merged.validate()?;
+        if !confirm("Merge?", yes)? {
+            return Err(CliError::CmdError(
+                "merge cancelled, nothing was changed".into(),
+            ));
+        }
+        let mut staged = stage_bookings(logs, &moved)?;
+        let mut aliases = Self::mapping()?;
+        aliases.remove(&self.slug);
+        aliases.insert(merged.slug.clone(), merged);
+        staged.push(Self::stage_map(aliases)?);
+        commit_staged(staged)?;
+        output::note(format!(
+            "Merged {} into {} ({} hour logs moved)",
+            self.slug,
+            target,
+            moved.len()
+        ));
+        Ok(())
+    }
+
+    /// Hourly rate that applies to work done on `date`.
+    pub fn rate_on(&self, date: NaiveDate) -> &Money {
+        self.rate_changes
+            .iter()
+            .rev()
+            .find(|change| change.effective_from <= date)
+            .map_or(&self.hourly_rate, |change| &change.rate)
+    }
+
+    /// Hourly rate that applies to work done today.
+    pub fn current_rate(&self) -> &Money {
+        self.rate_on(today())
+    }
+
+    /// Adds a rate change, replacing any change scheduled for the same date.
+    pub fn schedule_rate(&mut self, effective_from: NaiveDate, rate: Money) {
+        self.rate_changes
+            .retain(|change| change.effective_from != effective_from);
+        self.rate_changes.push(RateChange {
+            effective_from,
+            rate,
+        });
+        self.rate_changes
+            .sort_by_key(|change| change.effective_from);
+    }
+
+    fn format_rates(&self) -> String {
+        let mut lines = vec![format!(
+            "  {:10} {}",
+            "initial",
+            self.hourly_rate.to_string().green()
+        )];
+        for change in &self.rate_changes {
+            lines.push(format!(
+                "  {:10} {}",
+                change.effective_from.to_string(),
+                change.rate.to_string().green()
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+fn today() -> NaiveDate {
+    Local::now().naive_local().date()
 }
 
+fn prompt_rate(msg: String, currency: &Currency, default: Option<Money>) -> Money {
+    let test_currency = currency.clone();
+    let prompt = input::<String>().msg(msg).add_err_test(
+        move |x| Money::parse(x, &test_currency).is_ok(),
+        "amount should be formatted as <amount> [currency] (e.g. 87.50 EUR)",
+    );
+    let rate = match default {
+        Some(rate) => prompt.default(rate.to_string()).get(),
+        None => prompt.get(),
+    };
+    Money::parse(&rate, currency).expect("rate is validated by the prompt")
+}
+
+pub const FILTER_FIELDS: [&str; 1] = ["contract"];
+pub const SORT_KEYS: [&str; 1] = ["no_sort"];
+
 #[derive(Debug, Clone)]
 pub enum F {
     NoFilter,
@@ -178,7 +554,10 @@ impl FromStr for S {
     type Err = CliError;
 
     fn from_str(input: &str) -> Result<Self> {
-        Ok(Self::NoSort)
+        match input {
+            "no_sort" => Ok(Self::NoSort),
+            s => Err(AliasError::InvalidSort(s.to_owned()).into()),
+        }
     }
 }
 
@@ -191,17 +570,153 @@ impl Filter<F, S> for Alias {
         Ok(mapping.values().cloned().collect::<Vec<Self>>())
     }
 
-    fn filter(items: Vec<Self>, method: F) -> Vec<Self> {
-        match method {
+    fn filter(items: Vec<Self>, method: F) -> Result<Vec<Self>> {
+        Ok(match method {
             F::NoFilter => items,
             F::Contractor(contractor) => items
                 .into_iter()
                 .filter(|item| item.contractor == contractor)
                 .collect(),
-        }
+        })
     }
 
-    fn sort(items: Vec<Self>, method: S) -> Vec<Self> {
+    fn sort(items: Vec<Self>, _method: S) -> Vec<Self> {
         items
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::data_dir;
+    use crate::generics::testing::{sample, with_data_dir};
+    use std::fs;
+
+    #[test]
+    fn rate_on_picks_latest_effective_change() {
+        let eur = Currency::default();
+        let mut alias = Alias {
+            slug: "web".into(),
+            contractor: "acme".into(),
+            short_description: "website".into(),
+            hourly_rate: Money::new(9000, eur.clone()),
+            rate_changes: vec![],
+        };
+        alias.schedule_rate(
+            NaiveDate::from_ymd(2020, 9, 1),
+            Money::new(11000, eur.clone()),
+        );
+        alias.schedule_rate(
+            NaiveDate::from_ymd(2020, 7, 1),
+            Money::new(10000, eur.clone()),
+        );
+        let rate_on = |y, m, d| alias.rate_on(NaiveDate::from_ymd(y, m, d)).minor;
+        assert_eq!(rate_on(2020, 6, 30), 9000);
+        assert_eq!(rate_on(2020, 7, 1), 10000);
+        assert_eq!(rate_on(2020, 8, 31), 10000);
+        assert_eq!(rate_on(2021, 1, 1), 11000);
+    }
+
+    #[test]
+    fn updating_the_rate_schedules_a_change() {
+        let alias = Alias {
+            slug: "web".into(),
+            contractor: "acme".into(),
+            short_description: "website".into(),
+            hourly_rate: Money::new(9000, Currency::default()),
+            rate_changes: vec![],
+        };
+        let fields = Fields {
+            rate: Some("120".into()),
+            ..Fields::default()
+        };
+        let updated = alias.update(&fields).unwrap();
+        assert_eq!(updated.hourly_rate.minor, 9000);
+        assert_eq!(updated.rate_on(today().pred()).minor, 9000);
+        assert_eq!(updated.current_rate().minor, 12000);
+        assert_eq!(updated.rate_changes.len(), 1);
+        let unchanged = Fields {
+            rate: Some("120 EUR".into()),
+            ..Fields::default()
+        };
+        assert_eq!(updated.update(&unchanged).unwrap().rate_changes.len(), 1);
+    }
+
+    #[test]
+    fn legacy_rates_take_the_contractor_currency() {
+        let contractors = "[acme]\nslug = \"acme\"\nname = \"Acme\"\ncurrency = \"JPY\"\n";
+        let aliases = "[web]\nslug = \"web\"\ncontractor = \"acme\"\nshort_description = \"\"\nhourly_rate = 9000\n\n\
+                       [ops]\nslug = \"ops\"\ncontractor = \"gone\"\nshort_description = \"\"\nhourly_rate = 90\n";
+        let files = [(Contractor::FILE, contractors), (Alias::FILE, aliases)];
+        let aliases = with_data_dir(&files, || Alias::mapping().unwrap());
+        assert_eq!(aliases["web"].hourly_rate.to_string(), "9000 JPY");
+        assert_eq!(aliases["ops"].hourly_rate.to_string(), "90.00 EUR");
+    }
+
+    #[test]
+    fn unknown_aliases_are_usage_errors() {
+        with_data_dir(&sample(), || {
+            // records are looked up when the command runs, not while parsing the arguments
+            let cmd = Cmd::from_iter_safe(&["alias", "detail", "nope"]).unwrap();
+            assert_eq!(cmd.exec().unwrap_err().exit_code(), 2);
+            let cmd = Cmd::from_iter_safe(&["alias", "rate", "show", "nope"]).unwrap();
+            assert_eq!(cmd.exec().unwrap_err().exit_code(), 2);
+        });
+    }
+
+    #[test]
+    fn changes_need_the_full_slug() {
+        with_data_dir(&sample(), || {
+            let detail = Cmd::from_iter_safe(&["alias", "detail", "sh"]).unwrap();
+            assert!(detail.exec().is_ok());
+            let delete = Cmd::from_iter_safe(&["alias", "delete", "sh"]).unwrap();
+            assert_eq!(delete.exec().unwrap_err().exit_code(), 2);
+            let rename = Cmd::from_iter_safe(&["alias", "rename", "sh", "store"]).unwrap();
+            assert!(rename.exec().is_err());
+            assert!(Alias::mapping().unwrap().contains_key("shop"));
+        });
+    }
+
+    #[test]
+    fn rename_moves_the_bookings() {
+        with_data_dir(&sample(), || {
+            Alias::retrieve_exact("web")
+                .unwrap()
+                .rename("site")
+                .unwrap();
+            let aliases = Alias::mapping().unwrap();
+            assert!(!aliases.contains_key("web"));
+            assert_eq!(aliases["site"].rate_changes.len(), 1);
+            let logs = HourLog::mapping().unwrap();
+            assert_eq!(logs["w1"].alias, "site");
+            assert_eq!(logs["w2"].alias, "site");
+            assert_eq!(logs["a1"].alias, "api");
+            // slugs that are taken or invalid are refused before anything is written
+            let api = Alias::retrieve_exact("api").unwrap();
+            assert!(api.clone().rename("site").is_err());
+            assert!(api.rename("Not a slug").is_err());
+            assert_eq!(HourLog::mapping().unwrap()["a1"].alias, "api");
+            let leftovers = fs::read_dir(data_dir().unwrap())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| name.ends_with(".staged") || name.ends_with(".backup"))
+                .collect::<Vec<String>>();
+            assert!(leftovers.is_empty(), "{:?}", leftovers);
+        });
+    }
+
+    #[test]
+    fn merge_stays_within_a_contractor() {
+        with_data_dir(&sample(), || {
+            let shop = Alias::retrieve_exact("shop").unwrap();
+            let web = Alias::retrieve_exact("web").unwrap();
+            assert!(shop.merge(web.clone(), true).is_err());
+            assert_eq!(HourLog::mapping().unwrap()["s1"].alias, "shop");
+            let api = Alias::retrieve_exact("api").unwrap();
+            api.merge(web, true).unwrap();
+            let aliases = Alias::mapping().unwrap();
+            assert!(!aliases.contains_key("api"));
+            assert_eq!(HourLog::mapping().unwrap()["a1"].alias, "web");
+        });
+    }
+}
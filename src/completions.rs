@@ -0,0 +1,332 @@
+//! Shell completions. The scripts are generated from the `StructOpt` definitions and extended
+//! with a call to the hidden `__complete` command, which reads slugs, ids and filter
+//! directives from the data files, so completions follow the records as they change.
+use crate::alias::{self, Alias};
+use crate::contractors::{self, Contractor};
+use crate::generics::{Crud, Result};
+use crate::hours::{self, HourLog};
+use crate::invoice::{self, Invoice};
+use crate::output;
+use structopt::clap::{App, Shell};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Shell to generate completions for ( bash | zsh | fish )
+    #[structopt(possible_values = &["bash", "zsh", "fish"])]
+    shell: Shell,
+}
+
+/// Asks `__complete` first and falls back to the generated completions when it has nothing.
+const BASH: &str = r#"
+_bookit_dynamic() {
+    local line="${COMP_LINE:0:COMP_POINT}" words cur candidates
+    read -ra words <<< "$line"
+    [[ "$line" == *" " ]] && words+=("")
+    cur="${words[-1]}"
+    candidates=$(bookit __complete "${words[@]:1}" 2>/dev/null)
+    if [[ -z "$candidates" ]]; then
+        _bookit "$@"
+        return
+    fi
+    COMPREPLY=( $(compgen -W "$candidates" -- "$cur") )
+    # bash only replaces the text after the last colon of a filter directive
+    if [[ "$cur" == *:* && "$COMP_WORDBREAKS" == *:* ]]; then
+        local colon_prefix="${cur%"${cur##*:}"}"
+        COMPREPLY=( "${COMPREPLY[@]#"$colon_prefix"}" )
+    fi
+}
+complete -F _bookit_dynamic -o bashdefault -o default bookit
+"#;
+
+const ZSH: &str = r#"
+_bookit_dynamic() {
+    local -a candidates
+    candidates=(${(f)"$(bookit __complete "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    if (( ${#candidates} )); then
+        compadd -a candidates
+    else
+        _bookit "$@"
+    fi
+}
+compdef _bookit_dynamic bookit
+_bookit_dynamic "$@"
+"#;
+
+const FISH: &str = r#"
+complete -c bookit -f -a '(bookit __complete (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)'
+"#;
+
+impl Cmd {
+    pub fn exec(&self, mut app: App) -> Result<()> {
+        let mut script = vec![];
+        app.gen_completions_to("bookit", self.shell, &mut script);
+        let script = String::from_utf8_lossy(&script);
+        let script = script.trim_end();
+        let rendered = match self.shell {
+            Shell::Bash => format!("{}\n{}", script, BASH),
+            // the generated script ends by calling `_bookit`, the wrapper takes its place
+            Shell::Zsh => format!(
+                "{}\n{}",
+                script.trim_end_matches("_bookit \"$@\"").trim_end(),
+                ZSH
+            ),
+            Shell::Fish => format!("{}\n{}", script, FISH),
+            _ => script.to_string(),
+        };
+        output::emit(rendered.trim_end())
+    }
+}
+
+/// Prints the candidates for `bookit __complete <words>`, the words of the command line after
+/// `bookit` with the one being completed last.
+pub fn complete(app: &App, words: &[String]) -> Result<()> {
+    output::emit(&candidates(app, words).join("\n"))
+}
+
+/// Candidates for the last word of a `bookit shell` line: the live candidates of `__complete`,
+/// otherwise the subcommands or long flags of the command typed so far.
+pub fn complete_line(app: &App, words: &[String]) -> Vec<String> {
+    let found = candidates(app, words);
+    if !found.is_empty() {
+        return found;
+    }
+    let (current, before) = match words.split_last() {
+        Some(split) => split,
+        None => return vec![],
+    };
+    let mut app = app;
+    for word in before {
+        if let Some(sub) = app.p.subcommands.iter().find(|sub| sub.get_name() == word) {
+            app = sub;
+        }
+    }
+    let found = if current.starts_with('-') {
+        let flags = app.p.flags.iter().map(|flag| flag.s.long);
+        let opts = app.p.opts.iter().map(|opt| opt.s.long);
+        flags
+            .chain(opts)
+            .flatten()
+            .map(|long| format!("--{}", long))
+            .collect::<Vec<String>>()
+    } else {
+        app.p
+            .subcommands
+            .iter()
+            .map(|sub| sub.get_name().to_string())
+            .filter(|name| name != "help")
+            .collect()
+    };
+    found
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current.as_str()))
+        .collect()
+}
+
+/// Candidates for the last word given the words before it; empty when the generated
+/// completions should handle it (subcommands, flags, free text). The words are matched
+/// against the clap definition of the commands, so every argument that names a record is
+/// completed by its name (see `values`).
+fn candidates(app: &App, words: &[String]) -> Vec<String> {
+    let (current, before) = match words.split_last() {
+        Some(split) => split,
+        None => return vec![],
+    };
+    // the commands on the line, from `bookit` down; options of all of them may appear
+    let mut commands = vec![app];
+    let mut positionals = 0;
+    let mut value_of = None;
+    for word in before {
+        if value_of.take().is_some() {
+            continue;
+        }
+        let command = commands[commands.len() - 1];
+        if word.starts_with('-') {
+            if !word.contains('=') {
+                value_of = commands.iter().find_map(|command| option(command, word));
+            }
+        } else if let (0, Some(sub)) = (positionals, subcommand(command, word)) {
+            commands.push(sub);
+        } else {
+            positionals += 1;
+        }
+    }
+    let group = match commands.get(1).map(|command| command.get_name()) {
+        Some("export") => "hours",
+        Some(group) => group,
+        None => "",
+    };
+    let name = match value_of {
+        Some(name) => Some(name),
+        None => commands[commands.len() - 1]
+            .p
+            .positionals
+            .values()
+            .nth(positionals)
+            .map(|positional| positional.b.name),
+    };
+    name.map(|name| values(group, name))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current.as_str()))
+        .collect()
+}
+
+fn subcommand<'a, 'b>(command: &'a App<'a, 'b>, name: &str) -> Option<&'a App<'a, 'b>> {
+    command
+        .p
+        .subcommands
+        .iter()
+        .find(|sub| sub.get_name() == name)
+}
+
+/// Name of the option `word` (e.g. "-f" or "--where") when it takes a value.
+fn option<'a>(command: &App<'a, '_>, word: &str) -> Option<&'a str> {
+    command
+        .p
+        .opts
+        .iter()
+        .find(|opt| match word.strip_prefix("--") {
+            Some(long) => opt.s.long == Some(long),
+            None => word.len() == 2 && opt.s.short == word.chars().nth(1),
+        })
+        .map(|opt| opt.b.name)
+}
+
+/// Candidates for the argument called `name` of a command in `group` (e.g. "alias").
+fn values(group: &str, name: &str) -> Vec<String> {
+    match (group, name) {
+        (_, "filters") => filter_directives(group),
+        (_, "sort") => sort_keys(group),
+        ("alias", "from" | "into") | (_, "alias") => slugs::<Alias>(),
+        ("contractors", "from" | "into") | (_, "contractor") => slugs::<Contractor>(),
+        ("hours", "slug") | ("edit", "id") => slugs::<HourLog>(),
+        (_, "invoice" | "number") => slugs::<Invoice>(),
+        ("invoice", "status") => to_strings(&invoice::STATUSES),
+        _ => vec![],
+    }
+}
+
+fn slugs<T: Crud>() -> Vec<String> {
+    T::mapping().map(T::available_slugs).unwrap_or_default()
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn filter_directives(entity: &str) -> Vec<String> {
+    let fields: &[&str] = match entity {
+        "alias" => &alias::FILTER_FIELDS,
+        "contractors" => return vec!["nofilter".into()],
+        "hours" => &hours::FILTER_FIELDS,
+        "invoice" => &invoice::FILTER_FIELDS,
+        _ => &[],
+    };
+    fields
+        .iter()
+        .flat_map(|field| {
+            let values = match *field {
+                "contract" | "contractor" => slugs::<Contractor>(),
+                "alias" => slugs::<Alias>(),
+                "billed" => to_strings(&["yes", "no"]),
+                "status" => to_strings(&invoice::STATUSES),
+                _ => vec![],
+            };
+            if values.is_empty() {
+                vec![format!("{}::", field)]
+            } else {
+                values
+                    .iter()
+                    .map(|value| format!("{}::{}", field, value))
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+fn sort_keys(entity: &str) -> Vec<String> {
+    match entity {
+        "alias" => to_strings(&alias::SORT_KEYS),
+        "contractors" => to_strings(&contractors::SORT_KEYS),
+        "hours" => to_strings(&hours::SORT_KEYS),
+        "invoice" => to_strings(&invoice::SORT_KEYS),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+
+    /// Arguments that take free text or values that are not records.
+    const FREE: [&str; 6] = ["new", "rate", "time", "source", "file", "shell"];
+
+    fn words(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    fn complete(line: &str) -> Vec<String> {
+        candidates(&crate::Opt::clap(), &words(line))
+    }
+
+    #[test]
+    fn completes_flag_values_and_keys() {
+        assert_eq!(complete("hours show -s t"), vec!["ts", "timestamp"]);
+        assert_eq!(
+            complete("invoice show -q -f status::"),
+            vec![
+                "status::draft",
+                "status::sent",
+                "status::paid",
+                "status::void"
+            ]
+        );
+        assert_eq!(
+            complete("export hours --format csv -f billed"),
+            vec!["billed::yes", "billed::no"]
+        );
+        assert_eq!(complete("invoice status 2024-001 p"), vec!["paid"]);
+        assert_eq!(
+            complete("hours delete -y --include-billed --where billed::y"),
+            vec!["billed::yes"]
+        );
+        assert!(complete("hours book -m ").is_empty());
+        assert!(complete("alias ").is_empty());
+    }
+
+    #[test]
+    fn completes_every_argument_that_names_a_record() {
+        // every positional argument of every command, with the number of candidates
+        fn walk(command: &App, path: &str, found: &mut Vec<(String, usize)>) {
+            for (i, positional) in command.p.positionals.values().enumerate() {
+                let name = positional.b.name;
+                if !FREE.contains(&name) {
+                    let line = format!("{} {}", path, "x ".repeat(i));
+                    found.push((format!("{} <{}>", path, name), complete(&line).len()));
+                }
+            }
+            for sub in &command.p.subcommands {
+                walk(sub, format!("{} {}", path, sub.get_name()).trim(), found);
+            }
+        }
+        let mut found = vec![];
+        with_data_dir(&sample(), || walk(&crate::Opt::clap(), "", &mut found));
+        let missing = found
+            .iter()
+            .filter(|(_, count)| *count == 0)
+            .collect::<Vec<_>>();
+        assert!(missing.is_empty(), "nothing to complete for {:?}", missing);
+        assert!(found.iter().any(|(line, _)| line == "edit alias <alias>"));
+        assert!(found
+            .iter()
+            .any(|(line, _)| line == "invoice status <status>"));
+        with_data_dir(&sample(), || {
+            assert_eq!(complete("edit hours w"), vec!["w1", "w2"]);
+            assert_eq!(complete("contractors merge acme g"), vec!["globex"]);
+            assert_eq!(complete("alias merge web s"), vec!["shop"]);
+            assert_eq!(complete("hours set --alias a"), vec!["api"]);
+        });
+    }
+}
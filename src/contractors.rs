@@ -1,9 +1,14 @@
+use crate::alias::Alias;
 use crate::errors::CliError;
 use crate::generics::{
-    add_subject, delete_subject, update_subject, view_filtered_set, view_subject, Crud, Filter,
-    Result, View,
+    add_subject, commit_staged, confirm, reconcile, update_subject, view_filtered_set,
+    view_subject, Crud, Filter, Result, Staged, View,
 };
-use crate::utils::slugify;
+use crate::invoice::Invoice;
+use crate::money::Currency;
+use crate::output::{self, Columns};
+use crate::site;
+use crate::utils::{check_slug, interactive, missing_flag, slugify, unknown_key};
 use colored::*;
 use read_input::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -12,20 +17,122 @@ use std::str::FromStr;
 use structopt::StructOpt;
 use toml::{from_str as from_toml, to_string as to_toml};
 
+enum ContractorError {
+    InvalidFilterField(String),
+    InvalidSort(String),
+}
+
+impl From<ContractorError> for CliError {
+    fn from(err: ContractorError) -> Self {
+        match err {
+            ContractorError::InvalidFilterField(f) => {
+                unknown_key("cannot filter on", &f, &FILTER_FIELDS)
+            }
+            ContractorError::InvalidSort(s) => unknown_key("cannot sort on", &s, &SORT_KEYS),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Contractor {
     pub slug: String,
     pub name: String,
+    /// Currency used for new aliases of this contractor
+    #[serde(default)]
+    pub currency: Currency,
+    /// Number of days after issuing an invoice before payment is due
+    #[serde(default = "default_payment_terms")]
+    pub payment_terms: u32,
+    /// Language in which invoices are written (e.g. "en" | "nl")
+    #[serde(default = "default_language")]
+    pub language: String,
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub vat_id: Option<String>,
+    #[serde(default)]
+    pub registration_number: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Invoice template file name in `$BOOKIT_DIR/templates`
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+fn default_payment_terms() -> u32 {
+    30
+}
+
+fn default_language() -> String {
+    "en".into()
+}
+
+/// Contractor details given as flags; prompts are only shown for the ones left out.
+/// Optional details are cleared with "-".
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct Fields {
+    /// Contractor name
+    #[structopt(long = "name")]
+    name: Option<String>,
+    /// Default currency (e.g. "EUR")
+    #[structopt(long = "currency")]
+    currency: Option<Currency>,
+    /// Payment terms in days
+    #[structopt(long = "payment-terms")]
+    payment_terms: Option<u32>,
+    /// Invoice language (e.g. "en" | "nl")
+    #[structopt(long = "language")]
+    language: Option<String>,
+    #[structopt(long = "address")]
+    address: Option<String>,
+    #[structopt(long = "email")]
+    email: Option<String>,
+    #[structopt(long = "vat-id")]
+    vat_id: Option<String>,
+    #[structopt(long = "registration-number")]
+    registration_number: Option<String>,
+    #[structopt(long = "notes")]
+    notes: Option<String>,
+    /// Invoice template file name in $BOOKIT_DIR/templates
+    #[structopt(long = "template")]
+    template: Option<String>,
+}
+
+impl Fields {
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.currency.is_none()
+            && self.payment_terms.is_none()
+            && self.language.is_none()
+            && self.address.is_none()
+            && self.email.is_none()
+            && self.vat_id.is_none()
+            && self.registration_number.is_none()
+            && self.notes.is_none()
+            && self.template.is_none()
+    }
 }
 
 #[derive(StructOpt, Debug)]
 pub enum Cmd {
-    /// Create a new contractor interactively
+    /// Create a new contractor, prompting for details not given as flags
     #[structopt(name = "add")]
-    Create,
-    /// Update an existing contractor interactively
+    Create {
+        /// Contractor reference (lowercase and no spaces), derived from the name by default
+        #[structopt(long = "slug")]
+        slug: Option<String>,
+        #[structopt(flatten)]
+        fields: Fields,
+    },
+    /// Update the given details of a contractor, or all of them interactively
     #[structopt(name = "update")]
-    Update { contractor: Contractor },
+    Update {
+        contractor: String,
+        #[structopt(flatten)]
+        fields: Fields,
+    },
     /// View a collection of contractors
     #[structopt(name = "show")]
     Show {
@@ -36,31 +143,117 @@ pub enum Cmd {
     },
     /// View detailed contractor stats
     #[structopt(name = "detail")]
-    Detail { contractor: Contractor },
+    Detail { contractor: String },
     /// Delete a contractor
     #[structopt(name = "delete")]
-    Delete { contractor: Contractor },
+    Delete { contractor: String },
+    /// Change the slug of a contractor and of its aliases and invoices
+    #[structopt(name = "rename")]
+    Rename { contractor: String, new: String },
+    /// Move the aliases and invoices of a contractor to another contractor and delete it
+    #[structopt(name = "merge")]
+    Merge {
+        from: String,
+        into: String,
+        /// Keep the details of <into> and merge without asking
+        #[structopt(short = "y", long = "yes")]
+        yes: bool,
+    },
 }
 
 impl Cmd {
     pub fn exec(&self) -> Result<()> {
         match self {
-            Self::Create => add_subject(Contractor::new()?)?,
-            Self::Delete { contractor } => delete_subject::<Contractor>(&contractor.slug)?,
-            Self::Update { contractor } => update_subject::<Contractor>(&contractor.slug)?,
-            Self::Detail { contractor } => {
-                view_subject::<Contractor>(Some(contractor.slug.clone()))?
+            Self::Create { slug, fields } => {
+                let contractor = Contractor::new(slug.clone(), fields)?;
+                contractor.validate()?;
+                add_subject(contractor)?
             }
+            Self::Delete { contractor } => {
+                let contractor = Contractor::retrieve_exact(contractor)?;
+                let slug = contractor.slug.clone();
+                contractor.delete()?;
+                site::set_passphrase(&slug, None)?
+            }
+            Self::Update { contractor, fields } if fields.is_empty() => {
+                if !interactive() {
+                    return Err(CliError::CmdError(
+                        "nothing to update, give the new details as flags".into(),
+                    ));
+                }
+                update_subject::<Contractor>(contractor)?
+            }
+            Self::Update { contractor, fields } => {
+                let contractor = Contractor::retrieve_exact(contractor)?.fill(fields, false);
+                contractor.validate()?;
+                contractor.overwrite()?
+            }
+            Self::Detail { contractor } => view_subject::<Contractor>(Some(contractor.clone()))?,
             Self::Show { filters, sort } => {
                 view_filtered_set::<Contractor, F, S>(filters.to_vec(), sort.clone())?
             }
+            Self::Rename { contractor, new } => {
+                Contractor::retrieve_exact(contractor)?.rename(new)?
+            }
+            Self::Merge { from, into, yes } => {
+                Contractor::retrieve_exact(from)?.merge(Contractor::retrieve_exact(into)?, *yes)?
+            }
         };
         Ok(())
     }
 }
 
+/// The aliases and invoices of a contractor pointed at another slug. Nothing is written
+/// until `stage`, so a cancelled command leaves no files behind.
+struct References {
+    aliases: HashMap<String, Alias>,
+    invoices: HashMap<String, Invoice>,
+    moved_aliases: usize,
+    moved_invoices: usize,
+}
+
+impl References {
+    /// The aliases and invoices of contractor `old` pointing to `new` instead.
+    fn moved(old: &str, new: &str) -> Result<Self> {
+        let mut aliases = Alias::mapping()?;
+        let mut moved_aliases = 0;
+        for alias in aliases.values_mut().filter(|alias| alias.contractor == old) {
+            alias.contractor = new.to_string();
+            moved_aliases += 1;
+        }
+        let mut invoices = Invoice::mapping()?;
+        let mut moved_invoices = 0;
+        for invoice in invoices
+            .values_mut()
+            .filter(|invoice| invoice.contractor == old)
+        {
+            invoice.contractor = new.to_string();
+            moved_invoices += 1;
+        }
+        Ok(Self {
+            aliases,
+            invoices,
+            moved_aliases,
+            moved_invoices,
+        })
+    }
+
+    /// Stages the files with moved references; files without them are not staged.
+    fn stage(self) -> Result<Vec<Staged>> {
+        let mut staged = vec![];
+        if self.moved_aliases > 0 {
+            staged.push(Alias::stage_map(self.aliases)?);
+        }
+        if self.moved_invoices > 0 {
+            staged.push(Invoice::stage_map(self.invoices)?);
+        }
+        Ok(staged)
+    }
+}
+
 impl Crud for Contractor {
     const FILE: &'static str = "contractors_test.toml";
+    const RESOLVE_PREFIX: bool = true;
 
     fn identifier(&self) -> String {
         self.slug.to_owned()
@@ -74,16 +267,43 @@ impl Crud for Contractor {
         Ok(to_toml(&map)?)
     }
 
+    fn validate(&self) -> Result<()> {
+        check_slug(&self.slug)?;
+        if self.name.trim().is_empty() {
+            return Err(CliError::CmdError("contractor name cannot be empty".into()));
+        }
+        Ok(())
+    }
+
     fn interactive_update(&self) -> Self {
         let name = input::<String>()
-            .msg("Contractor name: ")
+            .msg(format!("Contractor name: [{}]", self.name))
             .default(self.name.clone())
             .get();
-        let slug = self.slug.clone();
-        Self { name, slug }
+        Self {
+            name,
+            ..self.clone()
+        }
+        .fill(&Fields::default(), true)
     }
 }
 
+impl Columns for Contractor {
+    const COLUMNS: &'static [&'static str] = &[
+        "slug",
+        "name",
+        "currency",
+        "payment_terms",
+        "language",
+        "address",
+        "email",
+        "vat_id",
+        "registration_number",
+        "notes",
+        "template",
+    ];
+}
+
 impl View for Contractor {
     fn format_list_item(&self) -> String {
         format!(
@@ -92,33 +312,235 @@ impl View for Contractor {
             self.name.bold().blue()
         )
     }
-}
 
-impl FromStr for Contractor {
-    type Err = CliError;
-
-    fn from_str(s: &str) -> Result<Self> {
-        Ok(Self::retrieve(s)?)
+    fn format_detail(&self) -> String {
+        let optional = |value: &Option<String>| match value {
+            Some(v) => v.clone(),
+            None => "-".dimmed().to_string(),
+        };
+        let fields = vec![
+            ("Currency", self.currency.to_string()),
+            ("Payment terms", format!("{} days", self.payment_terms)),
+            ("Language", self.language.clone()),
+            ("Address", optional(&self.address)),
+            ("Email", optional(&self.email)),
+            ("VAT ID", optional(&self.vat_id)),
+            ("Registration", optional(&self.registration_number)),
+            ("Notes", optional(&self.notes)),
+            ("Template", optional(&self.template)),
+            (
+                "Site",
+                match site::passphrase(&self.slug) {
+                    Ok(Some(_)) => "protected".to_string(),
+                    _ => "-".dimmed().to_string(),
+                },
+            ),
+        ];
+        let mut lines = vec![self.format_list_item()];
+        for (label, value) in fields {
+            lines.push(format!("  {:14} {}", format!("{}:", label).bold(), value));
+        }
+        lines.join("\n")
     }
 }
 
 impl Contractor {
-    fn new() -> Result<Self> {
-        let name = input::<String>().msg("Contractor name: ").get();
-        let slug = slugify(name.clone());
-        let slug_msg = format!(
-            "Contractor reference (lowercase and no spaces) [{}]: ",
-            &slug
+    /// Moves the contractor to the slug `new` together with the references of its aliases
+    /// and invoices, writing all data files or none.
+    fn rename(mut self, new: &str) -> Result<()> {
+        check_slug(new)?;
+        Self::write_ok(new, false)?;
+        let old = std::mem::replace(&mut self.slug, new.to_string());
+        let references = References::moved(&old, new)?;
+        let (aliases, invoices) = (references.moved_aliases, references.moved_invoices);
+        let mut staged = references.stage()?;
+        let mut contractors = Self::mapping()?;
+        contractors.remove(&old);
+        contractors.insert(self.slug.clone(), self);
+        staged.push(Self::stage_map(contractors)?);
+        commit_staged(staged)?;
+        site::move_passphrase(&old, new)?;
+        output::note(format!(
+            "Renamed {} to {} ({} aliases and {} invoices updated)",
+            old, new, aliases, invoices
+        ));
+        Ok(())
+    }
+
+    /// Moves the aliases and invoices of this contractor to `into` and deletes it, after a
+    /// preview and confirmation. Differing details are picked interactively unless `yes` is
+    /// set, in which case those of `into` are kept.
+    fn merge(self, into: Contractor, yes: bool) -> Result<()> {
+        if self.slug == into.slug {
+            return Err(CliError::CmdError(
+                "cannot merge a contractor into itself".into(),
+            ));
+        }
+        if self.currency != into.currency {
+            return Err(CliError::CmdError(format!(
+                "{} bills in {} and {} in {}, contractors with different currencies cannot be merged",
+                self.slug.yellow().bold(),
+                self.currency,
+                into.slug.yellow().bold(),
+                into.currency
+            )));
+        }
+        let references = References::moved(&self.slug, &into.slug)?;
+        let (aliases, invoices) = (references.moved_aliases, references.moved_invoices);
+        output::note(format!(
+            "Merging {} into {}: {} aliases and {} invoices move to {} and {} is deleted",
+            self.slug.yellow().bold(),
+            into.slug.green().bold(),
+            aliases,
+            invoices,
+            into.slug,
+            self.slug
+        ));
+        let target = into.slug.clone();
+        let merged = reconcile(&self, into, !yes && interactive())?;
+        merged.validate()?;
+        if !confirm("Merge?", yes)? {
+            return Err(CliError::CmdError(
+                "merge cancelled, nothing was changed".into(),
+            ));
+        }
+        let mut staged = references.stage()?;
+        let mut contractors = Self::mapping()?;
+        contractors.remove(&self.slug);
+        contractors.insert(merged.slug.clone(), merged);
+        staged.push(Self::stage_map(contractors)?);
+        commit_staged(staged)?;
+        site::move_passphrase(&self.slug, &target)?;
+        output::note(format!(
+            "Merged {} into {} ({} aliases and {} invoices moved)",
+            self.slug, target, aliases, invoices
+        ));
+        Ok(())
+    }
+
+    fn new(slug: Option<String>, fields: &Fields) -> Result<Self> {
+        let prompt = interactive();
+        let name = match (&fields.name, prompt) {
+            (Some(name), _) => name.clone(),
+            (None, true) => input::<String>().msg("Contractor name: ").get(),
+            (None, false) => return Err(missing_flag("name")),
+        };
+        let slug = match (slug, prompt) {
+            (Some(slug), _) => slug,
+            (None, true) => {
+                let slug = slugify(name.clone());
+                let slug_msg = format!(
+                    "Contractor reference (lowercase and no spaces) [{}]: ",
+                    &slug
+                );
+                input::<String>()
+                    .add_test(|x| *x == slugify(x.into()))
+                    .msg(slug_msg)
+                    .default(slug)
+                    .get()
+            }
+            (None, false) => slugify(name.clone()),
+        };
+        Ok(Self::blank(slug, name).fill(fields, prompt))
+    }
+
+    pub fn blank(slug: String, name: String) -> Self {
+        Self {
+            slug,
+            name,
+            currency: Currency::default(),
+            payment_terms: default_payment_terms(),
+            language: default_language(),
+            address: None,
+            email: None,
+            vat_id: None,
+            registration_number: None,
+            notes: None,
+            template: None,
+        }
+    }
+
+    /// Takes the details given in `fields`; the others are prompted for with the current
+    /// values as defaults when `prompt` is set, and kept otherwise.
+    fn fill(self, fields: &Fields, prompt: bool) -> Self {
+        let optional = |label: &str, flag: &Option<String>, current: &Option<String>| match (
+            flag.as_deref(),
+            prompt,
+        ) {
+            (Some("-"), _) => None,
+            (Some(value), _) => Some(value.to_string()),
+            (None, true) => prompt_optional(label, current),
+            (None, false) => current.clone(),
+        };
+        let name = fields.name.clone().unwrap_or_else(|| self.name.clone());
+        let currency = match (&fields.currency, prompt) {
+            (Some(currency), _) => currency.clone(),
+            (None, true) => input::<Currency>()
+                .msg(format!("Default currency: [{}]", self.currency))
+                .default(self.currency.clone())
+                .get(),
+            (None, false) => self.currency.clone(),
+        };
+        let payment_terms = match (fields.payment_terms, prompt) {
+            (Some(days), _) => days,
+            (None, true) => input::<u32>()
+                .msg(format!("Payment terms in days: [{}]", self.payment_terms))
+                .default(self.payment_terms)
+                .get(),
+            (None, false) => self.payment_terms,
+        };
+        let language = match (&fields.language, prompt) {
+            (Some(language), _) => language.clone(),
+            (None, true) => input::<String>()
+                .msg(format!("Invoice language: [{}]", self.language))
+                .add_test(|x| !x.trim().is_empty())
+                .default(self.language.clone())
+                .get(),
+            (None, false) => self.language.clone(),
+        };
+        let address = optional("Address", &fields.address, &self.address);
+        let email = optional("Contact email", &fields.email, &self.email);
+        let vat_id = optional("VAT/tax ID", &fields.vat_id, &self.vat_id);
+        let registration_number = optional(
+            "Company registration number",
+            &fields.registration_number,
+            &self.registration_number,
         );
-        let slug = input::<String>()
-            .add_test(|x| *x == slugify(x.into()))
-            .msg(slug_msg)
-            .default(slug)
-            .get();
-        Ok(Self { slug, name })
+        let notes = optional("Notes", &fields.notes, &self.notes);
+        let template = optional("Invoice template file", &fields.template, &self.template);
+        Self {
+            name,
+            currency,
+            payment_terms,
+            language,
+            address,
+            email,
+            vat_id,
+            registration_number,
+            notes,
+            template,
+            ..self
+        }
     }
 }
 
+/// Prompts for an optional field; an empty answer keeps the current value and "-" clears it.
+fn prompt_optional(label: &str, current: &Option<String>) -> Option<String> {
+    let shown = current.clone().unwrap_or_else(|| "-".into());
+    let answer = input::<String>()
+        .msg(format!("{}: [{}]", label, shown))
+        .default(shown)
+        .get();
+    match answer.trim() {
+        "" | "-" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// Contractors have no filters yet; `nofilter` is accepted for symmetry with the others.
+pub const FILTER_FIELDS: [&str; 0] = [];
+pub const SORT_KEYS: [&str; 1] = ["no_sort"];
+
 #[derive(Debug, Clone)]
 pub enum F {
     NoFilter,
@@ -128,7 +550,13 @@ impl FromStr for F {
     type Err = CliError;
 
     fn from_str(input: &str) -> Result<Self> {
-        Ok(F::NoFilter)
+        match input {
+            "nofilter" => Ok(F::NoFilter),
+            input => {
+                let field = input.split("::").next().unwrap_or(input);
+                Err(ContractorError::InvalidFilterField(field.to_owned()).into())
+            }
+        }
     }
 }
 
@@ -141,7 +569,10 @@ impl FromStr for S {
     type Err = CliError;
 
     fn from_str(input: &str) -> Result<Self> {
-        Ok(Self::NoSort)
+        match input {
+            "no_sort" => Ok(Self::NoSort),
+            s => Err(ContractorError::InvalidSort(s.to_owned()).into()),
+        }
     }
 }
 
@@ -154,11 +585,70 @@ impl Filter<F, S> for Contractor {
         Ok(mapping.values().cloned().collect::<Vec<Self>>())
     }
 
-    fn filter(items: Vec<Self>, method: F) -> Vec<Self> {
-        items
+    fn filter(items: Vec<Self>, _method: F) -> Result<Vec<Self>> {
+        Ok(items)
     }
 
-    fn sort(items: Vec<Self>, method: S) -> Vec<Self> {
+    fn sort(items: Vec<Self>, _method: S) -> Vec<Self> {
         items
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+
+    #[test]
+    fn rename_moves_aliases_invoices_and_passphrase() {
+        with_data_dir(&sample(), || {
+            site::set_passphrase("acme", Some("s3cret")).unwrap();
+            Contractor::retrieve_exact("acme")
+                .unwrap()
+                .rename("acme-corp")
+                .unwrap();
+            let contractors = Contractor::mapping().unwrap();
+            assert!(!contractors.contains_key("acme"));
+            assert_eq!(contractors["acme-corp"].slug, "acme-corp");
+            let aliases = Alias::mapping().unwrap();
+            assert_eq!(aliases["web"].contractor, "acme-corp");
+            assert_eq!(aliases["api"].contractor, "acme-corp");
+            assert_eq!(aliases["shop"].contractor, "globex");
+            let invoices = Invoice::mapping().unwrap();
+            assert_eq!(invoices["INV-2024-0001"].contractor, "acme-corp");
+            assert_eq!(invoices["INV-2024-0002"].contractor, "globex");
+            assert_eq!(
+                site::passphrase("acme-corp").unwrap().as_deref(),
+                Some("s3cret")
+            );
+            assert_eq!(site::passphrase("acme").unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn rename_to_a_taken_slug_changes_nothing() {
+        with_data_dir(&sample(), || {
+            let globex = Contractor::retrieve_exact("globex").unwrap();
+            assert!(globex.rename("acme").is_err());
+            assert_eq!(Contractor::mapping().unwrap().len(), 2);
+            assert_eq!(Alias::mapping().unwrap()["shop"].contractor, "globex");
+        });
+    }
+
+    #[test]
+    fn merge_needs_the_same_currency() {
+        with_data_dir(&sample(), || {
+            let acme = Contractor::retrieve_exact("acme").unwrap();
+            let mut globex = Contractor::retrieve_exact("globex").unwrap();
+            assert!(acme.clone().merge(globex.clone(), true).is_err());
+            assert_eq!(Alias::mapping().unwrap()["web"].contractor, "acme");
+            globex.currency = acme.currency.clone();
+            globex.overwrite().unwrap();
+            acme.merge(globex, true).unwrap();
+            assert!(!Contractor::mapping().unwrap().contains_key("acme"));
+            assert_eq!(Alias::mapping().unwrap()["web"].contractor, "globex");
+            let invoices = Invoice::mapping().unwrap();
+            assert_eq!(invoices["INV-2024-0001"].contractor, "globex");
+        });
+    }
+}
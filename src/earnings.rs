@@ -0,0 +1,191 @@
+use crate::alias::Alias;
+use crate::contractors::Contractor;
+use crate::errors::CliError;
+use crate::generics::{Crud, Filter, Result};
+use crate::hours::HourLog;
+use crate::money::Totals;
+use crate::utils::parse_date;
+use chrono::{Datelike, NaiveDate};
+use colored::*;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl FromStr for Period {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "month" => Ok(Self::Month),
+            "quarter" => Ok(Self::Quarter),
+            "year" => Ok(Self::Year),
+            _ => Err(CliError::Parse {
+                input: input.into(),
+                description: "period should be one of ( month | quarter | year )".into(),
+            }),
+        }
+    }
+}
+
+impl Period {
+    /// First and last day (inclusive) of the period containing `date`.
+    pub fn bounds(self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let (start_month, months) = match self {
+            Self::Month => (date.month(), 1),
+            Self::Quarter => (date.month0() / 3 * 3 + 1, 3),
+            Self::Year => (1, 12),
+        };
+        let start = NaiveDate::from_ymd(date.year(), start_month, 1);
+        let next_month = start_month + months;
+        let next = if next_month > 12 {
+            NaiveDate::from_ymd(date.year() + 1, next_month - 12, 1)
+        } else {
+            NaiveDate::from_ymd(date.year(), next_month, 1)
+        };
+        (start, next.pred())
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Reporting period ( month | quarter | year )
+    #[structopt(short = "p", long = "period", default_value = "month")]
+    period: Period,
+    /// Any date within the period to report on (e.g. "YYYY-MM-DD" | <weekday>)
+    #[structopt(short = "d", long = "date", default_value = "today", parse(try_from_str = parse_date))]
+    date: NaiveDate,
+    /// Only report on a single contractor
+    #[structopt(short = "c", long = "contractor")]
+    contractor: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    minutes: u32,
+    amount: Totals,
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        let (start, end) = self.period.bounds(self.date);
+        let contractor = self
+            .contractor
+            .as_deref()
+            .map(Contractor::retrieve)
+            .transpose()?;
+        let aliases = Alias::get_default_items(None, None)?
+            .into_iter()
+            .map(|alias| (alias.identifier(), alias))
+            .collect::<HashMap<String, Alias>>();
+        let mut report: BTreeMap<String, BTreeMap<String, Line>> = BTreeMap::new();
+        for log in HourLog::get_default_items(None, None)? {
+            if log.date < start || log.date > end {
+                continue;
+            }
+            let alias = match aliases.get(&log.alias) {
+                Some(alias) => alias,
+                None => continue,
+            };
+            if let Some(contractor) = &contractor {
+                if alias.contractor != contractor.slug {
+                    continue;
+                }
+            }
+            let line = report
+                .entry(alias.contractor.clone())
+                .or_default()
+                .entry(alias.slug.clone())
+                .or_default();
+            line.minutes += log.minutes;
+            line.amount.add(&log.amount(alias));
+        }
+        if report.is_empty() {
+            return Err(CliError::FilterNoResults);
+        }
+        println!(
+            "Earnings from {} to {}",
+            start.to_string().bold(),
+            end.to_string().bold()
+        );
+        let mut grand_total = Totals::default();
+        for (contractor, lines) in report {
+            println!("\n{}", contractor.cyan().bold());
+            let mut total = Line::default();
+            for (alias, line) in lines {
+                println!(
+                    "  {:12} {:>8} {:>14}",
+                    alias.red().bold(),
+                    format_minutes(line.minutes),
+                    line.amount
+                );
+                total.minutes += line.minutes;
+                total.amount.merge(&line.amount);
+            }
+            println!(
+                "  {:12} {:>8} {:>14}",
+                "total".bold(),
+                format_minutes(total.minutes),
+                total.amount.to_string().green().bold()
+            );
+            grand_total.merge(&total.amount);
+        }
+        println!(
+            "\n{} {}",
+            "Billable total:".bold(),
+            grand_total.to_string().green().bold()
+        );
+        Ok(())
+    }
+}
+
+fn format_minutes(minutes: u32) -> String {
+    format!("{}h{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_bounds() {
+        let date = NaiveDate::from_ymd(2020, 8, 14);
+        assert_eq!(
+            Period::Quarter.bounds(date),
+            (
+                NaiveDate::from_ymd(2020, 7, 1),
+                NaiveDate::from_ymd(2020, 9, 30)
+            )
+        );
+    }
+
+    #[test]
+    fn month_bounds_wrap_year() {
+        let date = NaiveDate::from_ymd(2020, 12, 3);
+        assert_eq!(
+            Period::Month.bounds(date),
+            (
+                NaiveDate::from_ymd(2020, 12, 1),
+                NaiveDate::from_ymd(2020, 12, 31)
+            )
+        );
+    }
+
+    #[test]
+    fn year_bounds() {
+        let date = NaiveDate::from_ymd(2020, 2, 29);
+        assert_eq!(
+            Period::Year.bounds(date),
+            (
+                NaiveDate::from_ymd(2020, 1, 1),
+                NaiveDate::from_ymd(2020, 12, 31)
+            )
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::alias::Alias;
+use crate::contractors::Contractor;
+use crate::errors::CliError;
+use crate::generics::{private_temp_file, Crud, Result};
+use crate::hours::{billed_error, HourLog};
+use crate::invoice::Invoice;
+use crate::output;
+use crate::utils::interactive;
+use colored::*;
+use dialoguer::Confirm;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use std::{env, fs};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum Cmd {
+    /// Edit an alias
+    #[structopt(name = "alias")]
+    Alias { alias: String },
+    /// Edit a contractor
+    #[structopt(name = "contractors", alias = "contractor")]
+    Contractors { contractor: String },
+    /// Edit an hour log
+    #[structopt(name = "hours")]
+    Hours {
+        id: String,
+        /// Also edit an hour log that is on an invoice (the invoice keeps its lines)
+        #[structopt(long = "include-billed")]
+        include_billed: bool,
+    },
+    /// Edit an invoice
+    #[structopt(name = "invoice")]
+    Invoice { number: String },
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        if !interactive() {
+            return Err(CliError::CmdError(
+                "editing needs a terminal, use update or the API in scripts".into(),
+            ));
+        }
+        match self {
+            Self::Alias { alias } => edit::<Alias>(alias, unchecked),
+            Self::Contractors { contractor } => edit::<Contractor>(contractor, unchecked),
+            Self::Hours { id, include_billed } => {
+                unbilled(id, *include_billed)?;
+                edit::<HourLog>(id, unchecked)
+            }
+            Self::Invoice { number } => edit::<Invoice>(number, status_change),
+        }
+    }
+}
+
+/// Accepts any edit that reads and validates.
+fn unchecked<T>(_: &T, _: &T) -> Result<()> {
+    Ok(())
+}
+
+/// Hour logs on an invoice only change with `--include-billed`, like deleting them.
+fn unbilled(id: &str, include_billed: bool) -> Result<()> {
+    match include_billed || !Invoice::billed_hours()?.contains(id) {
+        true => Ok(()),
+        false => Err(billed_error(format!("{} is", id.yellow().bold()))),
+    }
+}
+
+/// Edited invoices follow the same status lifecycle as `invoice status`.
+fn status_change(original: &Invoice, edited: &Invoice) -> Result<()> {
+    match edited.status == original.status {
+        true => Ok(()),
+        false => original.clone().set_status(edited.status),
+    }
+}
+
+/// Opens the record in `$VISUAL` or `$EDITOR` in the format of its data file and writes it
+/// back once it reads, validates and passes `check` (given the record before and after the
+/// edit); on errors the editor opens again with the edits kept.
+fn edit<T: Crud>(slug: &str, check: impl Fn(&T, &T) -> Result<()>) -> Result<()> {
+    let record = T::retrieve_exact(slug)?;
+    let id = record.identifier();
+    let extension = Path::new(T::FILE)
+        .extension()
+        .map_or("txt".into(), |ext| ext.to_string_lossy().into_owned());
+    let original = readable(
+        T::serialize(HashMap::from([(id.clone(), record.clone())]))?,
+        &extension,
+    )?;
+    let path = private_temp_file("bookit-edit", &extension, &original)?;
+    let result = loop {
+        if let Err(err) = run_editor(&path) {
+            break Err(err);
+        }
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(io_err) => break Err(CliError::Read(io_err)),
+        };
+        if text == original {
+            output::note("No changes");
+            break Ok(());
+        }
+        match parse::<T>(text, &id).and_then(|edited| check(&record, &edited).map(|()| edited)) {
+            Ok(edited) => {
+                break edited
+                    .overwrite()
+                    .map(|()| output::note(format!("{} saved", id)));
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                let again = Confirm::new()
+                    .with_prompt("Edit again? (the changes are discarded otherwise)")
+                    .default(true)
+                    .interact()
+                    .map_err(|dialoguer::Error::IO(io_err)| CliError::Read(io_err))?;
+                if !again {
+                    break Err(CliError::CmdError(format!(
+                        "{} was not changed",
+                        id.yellow()
+                    )));
+                }
+            }
+        }
+    };
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// JSON data files are written on a single line; records are edited pretty printed.
+fn readable(serialized: String, extension: &str) -> Result<String> {
+    match extension {
+        "json" => {
+            let value = serde_json::from_str::<serde_json::Value>(&serialized)?;
+            Ok(format!("{}\n", serde_json::to_string_pretty(&value)?))
+        }
+        _ => Ok(serialized),
+    }
+}
+
+/// The single record in the edited text, which must keep its identifier and validate.
+fn parse<T: Crud>(text: String, id: &str) -> Result<T> {
+    let mut records = T::deserialize(text)?.into_iter();
+    let record = match (records.next(), records.next()) {
+        (Some((_, record)), None) => record,
+        _ => {
+            return Err(CliError::CmdError(
+                "the file should contain exactly one record".into(),
+            ))
+        }
+    };
+    if record.identifier() != id {
+        return Err(CliError::CmdError(format!(
+            "the identifier {} cannot be changed here",
+            id.yellow()
+        )));
+    }
+    record.validate()?;
+    Ok(record)
+}
+
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    let mut args = editor.split_whitespace();
+    let program = match args.next() {
+        Some(program) => program,
+        None => return Err(CliError::BinaryError("EDITOR is empty".to_string())),
+    };
+    match Command::new(program).args(args).arg(path).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(CliError::BinaryError(format!(
+            "'{}' failed with {}",
+            editor, status
+        ))),
+        Err(io_err) => Err(CliError::BinaryError(format!(
+            "unable to run '{}': {}",
+            editor, io_err
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+    use crate::invoice::Status;
+
+    #[test]
+    fn pretty_prints_json_records() {
+        assert_eq!(
+            readable(r#"{"w1":{"minutes":60}}"#.into(), "json").unwrap(),
+            "{\n  \"w1\": {\n    \"minutes\": 60\n  }\n}\n"
+        );
+        assert_eq!(readable("a = 1\n".into(), "toml").unwrap(), "a = 1\n");
+        assert!(readable("{".into(), "json").is_err());
+    }
+
+    #[test]
+    fn reads_back_a_single_valid_record() {
+        with_data_dir(&sample(), || {
+            let edited = |text: &str| {
+                let mut aliases = Alias::mapping().unwrap();
+                let web = aliases.remove("web").unwrap();
+                let serialized = Alias::serialize(HashMap::from([("web".into(), web)])).unwrap();
+                serialized.replace("Website", text)
+            };
+            let alias = parse::<Alias>(edited("Web shop"), "web").unwrap();
+            assert_eq!(alias.short_description, "Web shop");
+            // the identifier stays the same
+            let renamed = edited("Web").replace("slug = \"web\"", "slug = \"www\"");
+            assert!(parse::<Alias>(renamed, "web").is_err());
+            // exactly one record
+            let both = format!("{}\n{}", edited("x"), edited("y").replace("[web]", "[www]"));
+            assert!(parse::<Alias>(both, "web").is_err());
+            assert!(parse::<Alias>(String::new(), "web").is_err());
+            // and it validates
+            let orphan = edited("x").replace("contractor = \"acme\"", "contractor = \"nope\"");
+            assert!(parse::<Alias>(orphan, "web").is_err());
+        });
+    }
+
+    #[test]
+    fn keeps_billed_hours_and_the_invoice_lifecycle() {
+        with_data_dir(&sample(), || {
+            assert!(unbilled("w1", false).is_err());
+            assert!(unbilled("w1", true).is_ok());
+            assert!(unbilled("w2", false).is_ok());
+
+            let invoices = Invoice::mapping().unwrap();
+            let sent = &invoices["INV-2024-0001"];
+            let mut edited = sent.clone();
+            assert!(status_change(sent, &edited).is_ok());
+            edited.status = Status::Paid;
+            assert!(status_change(sent, &edited).is_ok());
+            edited.status = Status::Draft;
+            assert!(status_change(sent, &edited).is_err());
+        });
+    }
+}
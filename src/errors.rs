@@ -16,6 +16,31 @@ pub enum CliError {
 
 impl Error for CliError {}
 
+impl CliError {
+    /// Exit code of the error category; the codes are listed in the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::BinaryError(_) => 1,
+            Self::CmdError(_) => 2,
+            Self::Parse { .. } => 3,
+            Self::Read(_) | Self::Write(_) => 4,
+            Self::Serialization(_) => 5,
+            Self::Env(..) => 6,
+            Self::FilterNoResults => 7,
+        }
+    }
+
+    /// The message without the terminal decoration ("└─[Usage Error] ") for places that show
+    /// errors on a single line (API responses, the dashboard, errors per row of an input).
+    pub fn message(&self) -> String {
+        let message = self.to_string();
+        let message = message
+            .split_once("] ")
+            .map_or(message.as_str(), |(_, m)| m);
+        message.trim().to_string()
+    }
+}
+
 fn arg_error(err_type: &str) -> ColoredString {
     format!("\n{}[{} Error]", "└─".red().bold(), err_type)
         .bold()
@@ -70,3 +95,15 @@ impl From<serde_json::Error> for CliError {
         Self::Serialization(err.to_string())
     }
 }
+
+impl From<csv::Error> for CliError {
+    fn from(err: csv::Error) -> CliError {
+        Self::Serialization(err.to_string())
+    }
+}
+
+impl From<handlebars::RenderError> for CliError {
+    fn from(err: handlebars::RenderError) -> CliError {
+        Self::Serialization(err.to_string())
+    }
+}
@@ -0,0 +1,198 @@
+use crate::alias::Alias;
+use crate::errors::CliError;
+use crate::generics::{Crud, Filter, Result};
+use crate::hours::{self, HourLog};
+use crate::invoice::format_hours;
+use crate::output::{self, Columns, Output};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub enum Cmd {
+    /// Export hour logs with their contractor, rate and amount
+    #[structopt(name = "hours")]
+    Hours {
+        #[structopt(short = "f")]
+        filters: Vec<hours::F>,
+        #[structopt(short = "s", default_value = "ts")]
+        sort: hours::S,
+        /// File format ( csv | tsv | json | jsonl )
+        #[structopt(long = "format", default_value = "csv")]
+        format: Output,
+        /// Output file, stdout when omitted
+        #[structopt(short = "o", long = "out", parse(from_os_str))]
+        out: Option<PathBuf>,
+    },
+}
+
+/// An hour log with the fields resolved through its alias. Amounts are plain decimals so
+/// spreadsheets read them as numbers; the currency has its own column.
+#[derive(Serialize)]
+struct HourRow<'a> {
+    #[serde(flatten)]
+    log: &'a HourLog,
+    hours: String,
+    contractor: Option<&'a str>,
+    currency: Option<String>,
+    rate: Option<String>,
+    amount: Option<String>,
+}
+
+impl Columns for HourRow<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "alias",
+        "minutes",
+        "date",
+        "message",
+        "ticket",
+        "branch",
+        "id",
+        "timestamp",
+        "hours",
+        "contractor",
+        "currency",
+        "rate",
+        "amount",
+    ];
+}
+
+impl<'a> HourRow<'a> {
+    fn new(log: &'a HourLog, aliases: &'a HashMap<String, Alias>) -> Self {
+        let alias = aliases.get(&log.alias);
+        Self {
+            log,
+            hours: format_hours(log.minutes),
+            contractor: alias.map(|alias| alias.contractor.as_str()),
+            currency: alias.map(|alias| alias.rate_on(log.date).currency.to_string()),
+            rate: alias.map(|alias| alias.rate_on(log.date).format_amount()),
+            amount: alias.map(|alias| log.amount(alias).format_amount()),
+        }
+    }
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        match self {
+            Self::Hours {
+                filters,
+                sort,
+                format,
+                out,
+            } => {
+                if *format == Output::Table {
+                    return Err(CliError::CmdError(
+                        "table is not an export format, use csv, tsv, json or jsonl".into(),
+                    ));
+                }
+                let logs =
+                    match HourLog::apply_filterset(HourLog::get_base_items()?, filters.to_vec()) {
+                        Err(CliError::FilterNoResults) => vec![],
+                        logs => logs?,
+                    };
+                let logs = HourLog::sort(logs, sort.clone());
+                let aliases = Alias::mapping()?;
+                let rows = logs
+                    .iter()
+                    .map(|log| HourRow::new(log, &aliases))
+                    .collect::<Vec<HourRow>>();
+                let rendered = format.render_list(&rows)?;
+                match out {
+                    Some(path) => {
+                        fs::write(path, format!("{}\n", rendered)).map_err(CliError::Write)?;
+                        output::note(format!(
+                            "Exported {} hour logs to {}",
+                            rows.len(),
+                            path.display()
+                        ))
+                    }
+                    None => output::emit(&rendered)?,
+                }
+            }
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::data_dir;
+    use crate::generics::testing::{assert_columns, sample, with_data_dir};
+    use std::str::FromStr;
+
+    fn export(filters: &[&str], format: Output) -> String {
+        let out = data_dir().unwrap().join("export");
+        Cmd::Hours {
+            filters: filters
+                .iter()
+                .map(|f| hours::F::from_str(f).unwrap())
+                .collect(),
+            sort: hours::S::from_str("ts").unwrap(),
+            format,
+            out: Some(out.clone()),
+        }
+        .exec()
+        .unwrap();
+        fs::read_to_string(out).unwrap()
+    }
+
+    #[test]
+    fn exports_resolved_rates_and_amounts() {
+        let (csv, jsonl) = with_data_dir(&sample(), || {
+            (
+                export(&[], Output::Csv),
+                export(&["alias::web"], Output::Jsonl),
+            )
+        });
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        let rows = reader
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                let field = |name: &str| {
+                    let column = headers.iter().position(|h| h == name).unwrap();
+                    record[column].to_string()
+                };
+                ["id", "contractor", "hours", "rate", "currency", "amount"]
+                    .map(field)
+                    .join(" ")
+            })
+            .collect::<Vec<String>>();
+        // newest first; w2 is booked after the rate change of web
+        assert_eq!(
+            rows,
+            vec![
+                "s1 globex 2.00 80.00 USD 160.00",
+                "a1 acme 1.50 100.00 EUR 150.00",
+                "w2 acme 0.50 120.00 EUR 60.00",
+                "w1 acme 1.00 90.00 EUR 90.00",
+            ]
+        );
+        let ids = jsonl
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].clone())
+            .collect::<Vec<serde_json::Value>>();
+        assert_eq!(ids, vec!["w2", "w1"]);
+        assert_eq!(
+            csv.lines().next(),
+            Some(HourRow::COLUMNS.join(",").as_str())
+        );
+    }
+
+    #[test]
+    fn rows_declare_their_columns() {
+        with_data_dir(&sample(), || {
+            let aliases = Alias::mapping().unwrap();
+            let logs = HourLog::mapping().unwrap();
+            let rows = logs
+                .values()
+                .map(|log| HourRow::new(log, &aliases))
+                .collect::<Vec<HourRow>>();
+            assert_columns(&rows);
+        });
+    }
+}
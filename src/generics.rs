@@ -1,28 +1,109 @@
 use crate::errors::CliError;
+use crate::output::{self, Columns, Output};
+use crate::utils::{did_you_mean, interactive, suggest};
 use colored::*;
+use dialoguer::{Confirm, FuzzySelect, Select};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::{env, fs, path};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+use std::{env, fs, io, path};
 
 pub type Result<T, E = CliError> = std::result::Result<T, E>;
 type Mapping<T> = HashMap<String, T>;
 
+/// Mappings that were read or written by this process with the `Stamp` of their file, so
+/// long running commands (`shell`, `serve`) only parse a file again when it changed.
+static MAPPINGS: LazyLock<Mutex<HashMap<path::PathBuf, Cached>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+type Cached = (Stamp, Box<dyn Any + Send>);
+
+/// Modification time and length of a file. The length catches writes within the resolution
+/// of the modification time, which is coarse on some file systems.
+type Stamp = (SystemTime, u64);
+
+fn stamp(path: &path::Path) -> io::Result<Stamp> {
+    let meta = fs::metadata(path)?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+fn cached<T: Clone + 'static>(path: &path::Path, stamp: Stamp) -> Option<Mapping<T>> {
+    match MAPPINGS.lock().ok()?.get(path) {
+        Some((at, map)) if *at == stamp => map.downcast_ref::<Mapping<T>>().cloned(),
+        _ => None,
+    }
+}
+
+fn cache<T: Send + 'static>(path: path::PathBuf, stamp: Stamp, map: Mapping<T>) {
+    if let Ok(mut mappings) = MAPPINGS.lock() {
+        mappings.insert(path, (stamp, Box::new(map)));
+    }
+}
+
+/// Directory holding all data files, taken from `BOOKIT_DIR`.
+pub fn data_dir() -> Result<path::PathBuf> {
+    match env::var("BOOKIT_DIR") {
+        Ok(dir) => Ok(path::PathBuf::from(dir)),
+        Err(var_error) => Err(CliError::Env("BOOKIT_DIR".to_string(), var_error)),
+    }
+}
+
+/// Writes `content` to a new file in the temp directory that only the current user can
+/// read. The name is random and the file must not exist yet, so nobody else can read the
+/// file or put another one in its place.
+pub fn private_temp_file(prefix: &str, extension: &str, content: &str) -> Result<path::PathBuf> {
+    loop {
+        let name = RandomState::new().build_hasher().finish();
+        let path = env::temp_dir().join(format!("{}-{:016x}.{}", prefix, name, extension));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path) {
+            Ok(mut file) => {
+                if let Err(io_err) = file.write_all(content.as_bytes()) {
+                    let _ = fs::remove_file(&path);
+                    return Err(CliError::Write(io_err));
+                }
+                return Ok(path);
+            }
+            Err(io_err) if io_err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(io_err) => return Err(CliError::Write(io_err)),
+        }
+    }
+}
+
 pub trait Crud
 where
     Self: std::marker::Sized,
-    Self: Clone,
+    Self: Clone + Send + 'static,
 {
     const FILE: &'static str;
+    /// Whether `retrieve` accepts an unambiguous prefix of a slug
+    const RESOLVE_PREFIX: bool = false;
     fn identifier(&self) -> String;
     fn deserialize(s: String) -> Result<Mapping<Self>>;
     fn serialize(map: HashMap<String, Self>) -> Result<String>;
     fn interactive_update(&self) -> Self;
 
+    /// Checks the record before it is written by commands that take records as data
+    /// rather than through prompts (e.g. references to other records exist).
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
     fn path() -> Result<path::PathBuf> {
-        let basedir = match env::var("BOOKIT_DIR") {
-            Ok(dir) => Ok(dir),
-            Err(var_error) => Err(CliError::Env("BOOKIT_DIR".to_string(), var_error)),
-        }?;
-        Ok(path::Path::new(&basedir).join(Self::FILE))
+        Ok(data_dir()?.join(Self::FILE))
     }
 
     fn file_content() -> Result<String> {
@@ -33,19 +114,44 @@ where
     }
 
     fn mapping() -> Result<Mapping<Self>> {
-        let content = Self::file_content()?;
-        let map = Crud::deserialize(content)?;
+        let path = Self::path()?;
+        let stamp = match stamp(&path) {
+            Err(io_err) if io_err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(io_err) => return Err(CliError::Read(io_err)),
+            Ok(stamp) => stamp,
+        };
+        if let Some(map) = cached(&path, stamp) {
+            return Ok(map);
+        }
+        let map: Mapping<Self> = Crud::deserialize(Self::file_content()?)?;
+        cache(path, stamp, map.clone());
         Ok(map)
     }
 
     fn commit_map(map: HashMap<String, Self>) -> Result<()> {
-        let s = Crud::serialize(map)?;
-        match fs::write(Self::path()?, s) {
-            Ok(()) => Ok(()),
+        let path = Self::path()?;
+        let s = Crud::serialize(map.clone())?;
+        match fs::write(&path, s) {
+            Ok(()) => {
+                if let Ok(stamp) = stamp(&path) {
+                    cache(path, stamp, map);
+                }
+                Ok(())
+            }
             Err(io_err) => Err(CliError::Write(io_err)),
         }
     }
 
+    /// Writes the mapping next to its data file; `commit_staged` puts it in place. Changes
+    /// that span several data files stage all of them first, so a failed write leaves every
+    /// data file as it was.
+    fn stage_map(map: HashMap<String, Self>) -> Result<Staged> {
+        let path = Self::path()?;
+        let temp = path.with_extension("staged");
+        fs::write(&temp, Crud::serialize(map)?).map_err(CliError::Write)?;
+        Ok(Staged { temp, path })
+    }
+
     fn add(&self) -> Result<()> {
         let slug = self.identifier();
         Self::write_ok(&slug, false)?;
@@ -60,7 +166,7 @@ where
         Self::write_ok(&slug, true)?;
         let mut mapping = Self::mapping()?;
         mapping.remove(&slug);
-        Ok(Self::commit_map(mapping)?)
+        Self::commit_map(mapping)
     }
 
     fn overwrite(&self) -> Result<()> {
@@ -69,7 +175,7 @@ where
         let mut mapping = Self::mapping()?;
         mapping.remove(&slug);
         mapping.insert(slug, self.clone());
-        Ok(Self::commit_map(mapping)?)
+        Self::commit_map(mapping)
     }
 
     fn write_ok(slug: &str, slug_expect: bool) -> Result<()> {
@@ -77,8 +183,9 @@ where
         match (slug_expect, map.contains_key(slug)) {
             (true, true) | (false, false) => Ok(()),
             (true, false) => Err(CliError::CmdError(format!(
-                "item with slug {} was not found",
-                slug.yellow().bold()
+                "item with slug {} was not found{}",
+                slug.yellow().bold(),
+                did_you_mean(&suggest(slug, map.keys().map(String::as_str)))
             ))),
             (false, true) => Err(CliError::CmdError(format!(
                 "item with slug {} already exists",
@@ -87,32 +194,92 @@ where
         }
     }
 
+    /// The record with slug `slug`, or the only one starting with it when `RESOLVE_PREFIX`
+    /// is set. Commands that only read or book use this.
     fn retrieve(slug: &str) -> Result<Self> {
-        let mapping = Self::mapping()?;
-        match mapping.get(slug) {
-            Some(obj) => Ok(obj.clone()),
-            None => {
-                let existing = Self::available_slugs(Self::mapping()?);
-                Err(CliError::CmdError(format!(
-                    "{} not found. Available values are: {}",
-                    slug.yellow().bold(),
-                    match existing.len() {
-                        0..=10 => format!("{}", existing.join(" | ").green()),
-                        _ => format!(
-                            "{} (output truncated...)",
-                            existing[0..10].to_vec().join(" | ").green()
-                        ),
-                    }
-                )))
-            }
-        }
+        find(slug, Self::RESOLVE_PREFIX)
+    }
+
+    /// The record with exactly slug `slug`, for commands that change or delete records.
+    fn retrieve_exact(slug: &str) -> Result<Self> {
+        find(slug, false)
     }
 
+    /// All slugs in alphabetical order.
     fn available_slugs(mapping: Mapping<Self>) -> Vec<String> {
-        mapping.keys().cloned().collect()
+        let mut slugs = mapping.keys().cloned().collect::<Vec<String>>();
+        slugs.sort();
+        slugs
     }
 }
 
+/// A data file written by `Crud::stage_map` that is not in place yet; dropping it without
+/// committing removes the written file.
+pub struct Staged {
+    temp: path::PathBuf,
+    path: path::PathBuf,
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.temp);
+    }
+}
+
+/// Moves staged data files in place. Each rename replaces a whole file at once and, unlike
+/// the writes before it, does not run out of space or produce a half-written file. The data
+/// files are copied to backups first; when a rename fails the files already replaced are
+/// restored from them, so either every file changes or none does.
+pub fn commit_staged(staged: Vec<Staged>) -> Result<()> {
+    let mut backups = vec![];
+    for file in &staged {
+        let backup = file.path.with_extension("backup");
+        match fs::copy(&file.path, &backup) {
+            Ok(_) => backups.push(Some(backup)),
+            Err(io_err) if io_err.kind() == io::ErrorKind::NotFound => backups.push(None),
+            Err(io_err) => {
+                backups.iter().flatten().for_each(|backup| {
+                    let _ = fs::remove_file(backup);
+                });
+                return Err(CliError::Write(io_err));
+            }
+        }
+    }
+    let mut result = Ok(());
+    let mut committed = 0;
+    for file in &staged {
+        if let Err(io_err) = fs::rename(&file.temp, &file.path) {
+            result = Err(CliError::Write(io_err));
+            break;
+        }
+        committed += 1;
+    }
+    for (i, (file, backup)) in staged.iter().zip(&backups).enumerate() {
+        let restore = result.is_err() && i < committed;
+        match (restore, backup) {
+            (true, Some(backup)) => {
+                if let Err(io_err) = fs::rename(backup, &file.path) {
+                    output::note(format!(
+                        "{} {} could not be restored ({}), the previous version is in {}",
+                        "[Write Warning]".yellow().bold(),
+                        file.path.display(),
+                        io_err,
+                        backup.display()
+                    ));
+                }
+            }
+            (true, None) => {
+                let _ = fs::remove_file(&file.path);
+            }
+            (false, Some(backup)) => {
+                let _ = fs::remove_file(backup);
+            }
+            (false, None) => (),
+        }
+    }
+    result
+}
+
 pub trait View
 where
     Self: std::marker::Sized,
@@ -139,20 +306,21 @@ where
     const DEFAULT_SORT: S;
     const DEFAULT_FILTER: F;
 
-    fn filter(items: Vec<Self>, method: F) -> Vec<Self>;
+    fn filter(items: Vec<Self>, method: F) -> Result<Vec<Self>>;
     fn sort(items: Vec<Self>, method: S) -> Vec<Self>;
     fn get_base_items() -> Result<Vec<Self>>;
 
     fn get_default_items(filter: Option<F>, sort: Option<S>) -> Result<Vec<Self>> {
-        let apply_filter = |v| Self::filter(v, Self::DEFAULT_FILTER);
-        let apply_sort = |v| Self::sort(v, Self::DEFAULT_SORT);
+        let filter = filter.unwrap_or(Self::DEFAULT_FILTER);
+        let sort = sort.unwrap_or(Self::DEFAULT_SORT);
+        let apply_filter = |v| Self::filter(v, filter);
+        let apply_sort = |v: Result<Vec<Self>>| v.map(|v| Self::sort(v, sort));
         let items = Self::get_base_items()?;
-        let items = pipe!(
+        pipe!(
             items
             => apply_filter
             => apply_sort
-        );
-        Ok(items)
+        )
     }
 
     fn apply_filterset(items: Vec<Self>, filters: Vec<F>) -> Result<Vec<Self>> {
@@ -162,60 +330,493 @@ where
             (_, _) => {
                 let head: F = filters.first().unwrap().clone();
                 let tail: Vec<F> = filters[1..].to_vec();
-                Self::apply_filterset(Self::filter(items, head), tail)
+                Self::apply_filterset(Self::filter(items, head)?, tail)
             }
         }
     }
 }
 
+/// `format_list_item` of every item without colors, for widgets that style text themselves.
+pub fn plain_list_items<T: View>(items: &[T]) -> Vec<String> {
+    let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+    colored::control::set_override(false);
+    let labels = items
+        .iter()
+        .map(|item| item.format_list_item())
+        .collect::<Vec<String>>();
+    colored::control::set_override(colorize);
+    labels
+}
+
+/// Lets the user choose a record with a fuzzy search over `format_list_item`, starting with
+/// `query` as search text.
+pub fn pick<T>(prompt: &str, query: &str) -> Result<T>
+where
+    T: Crud,
+    T: View,
+{
+    let mut items = T::mapping()?.into_values().collect::<Vec<T>>();
+    if items.is_empty() {
+        return Err(CliError::CmdError(format!(
+            "no {} to pick from yet",
+            prompt.to_lowercase()
+        )));
+    }
+    items.sort_by_key(|item| item.identifier());
+    // colors would end up in the search text
+    let labels = plain_list_items(&items);
+    let choice = FuzzySelect::new()
+        .with_prompt(prompt)
+        .items(&labels)
+        .with_initial_text(query)
+        .default(0)
+        .interact_opt()
+        .map_err(|dialoguer::Error::IO(io_err)| CliError::Read(io_err))?;
+    match choice {
+        Some(i) => Ok(items.swap_remove(i)),
+        None => Err(CliError::CmdError(format!(
+            "no {} picked",
+            prompt.to_lowercase()
+        ))),
+    }
+}
+
+/// Looks up `slug`, resolving an unambiguous prefix when `resolve_prefix` is set; the error
+/// lists close matches or the available slugs.
+fn find<T: Crud>(slug: &str, resolve_prefix: bool) -> Result<T> {
+    let mut mapping = T::mapping()?;
+    if let Some(obj) = mapping.remove(slug) {
+        return Ok(obj);
+    }
+    let existing = T::available_slugs(mapping.clone());
+    let matches = existing
+        .iter()
+        .filter(|s| !slug.is_empty() && s.starts_with(slug))
+        .collect::<Vec<&String>>();
+    match matches.as_slice() {
+        [only] if resolve_prefix => Ok(mapping.remove(*only).unwrap()),
+        [_, _, ..] if resolve_prefix => Err(CliError::CmdError(format!(
+            "{} is ambiguous, it could be {}",
+            slug.yellow().bold(),
+            matches
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<&str>>()
+                .join(" | ")
+                .green()
+        ))),
+        _ => {
+            let suggestions = suggest(slug, existing.iter().map(String::as_str));
+            if !suggestions.is_empty() {
+                return Err(CliError::CmdError(format!(
+                    "{} not found{}",
+                    slug.yellow().bold(),
+                    did_you_mean(&suggestions)
+                )));
+            }
+            Err(CliError::CmdError(format!(
+                "{} not found. Available values are: {}",
+                slug.yellow().bold(),
+                match existing.len() {
+                    0..=10 => format!("{}", existing.join(" | ").green()),
+                    _ => format!(
+                        "{} (output truncated...)",
+                        existing[0..10].to_vec().join(" | ").green()
+                    ),
+                }
+            )))
+        }
+    }
+}
+
+/// Record for `slug`, picked interactively when `slug` is omitted or an ambiguous prefix and
+/// stdin is a terminal.
+pub fn retrieve_or_pick<T>(slug: Option<&str>, prompt: &str) -> Result<T>
+where
+    T: Crud,
+    T: View,
+{
+    match slug {
+        Some(slug) => match T::retrieve(slug) {
+            Err(_) if interactive() && is_ambiguous::<T>(slug)? => pick(prompt, slug),
+            result => result,
+        },
+        None if interactive() => pick(prompt, ""),
+        None => Err(CliError::CmdError(format!(
+            "{} is required when stdin is not a terminal",
+            prompt.to_lowercase()
+        ))),
+    }
+}
+
+/// Asks to go ahead with a change that was previewed; `yes` (the `--yes` flag) answers for
+/// the user and is required when stdin is not a terminal.
+pub fn confirm(prompt: &str, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !interactive() {
+        return Err(CliError::CmdError(
+            "add --yes to confirm when stdin is not a terminal".into(),
+        ));
+    }
+    Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .map_err(|dialoguer::Error::IO(io_err)| CliError::Read(io_err))
+}
+
+/// `target` with, for every field (other than the slug) in which `source` differs, the value
+/// chosen by the user when `ask` is set; otherwise the values of `target` are kept. The
+/// differences are listed either way.
+pub fn reconcile<T>(source: &T, target: T, ask: bool) -> Result<T>
+where
+    T: Crud,
+    T: Serialize + DeserializeOwned,
+{
+    let (source_fields, mut fields) = match (
+        serde_json::to_value(source)?,
+        serde_json::to_value(&target)?,
+    ) {
+        (Value::Object(source_fields), Value::Object(fields)) => (source_fields, fields),
+        _ => return Ok(target),
+    };
+    let show = |value: &Value| match value {
+        Value::Null => "(none)".to_string(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    let (source_slug, target_slug) = (source.identifier(), target.identifier());
+    for (field, value) in source_fields {
+        let current = fields.get(&field).cloned().unwrap_or(Value::Null);
+        if field == "slug" || current == value {
+            continue;
+        }
+        if !ask {
+            output::note(format!(
+                "  {}: keeping {} over {}",
+                field,
+                show(&current).green(),
+                show(&value).yellow()
+            ));
+            continue;
+        }
+        let choice = Select::new()
+            .with_prompt(format!("{} differs", field))
+            .items(&[
+                format!("{} (from {})", show(&current), target_slug),
+                format!("{} (from {})", show(&value), source_slug),
+            ])
+            .default(0)
+            .interact()
+            .map_err(|dialoguer::Error::IO(io_err)| CliError::Read(io_err))?;
+        if choice == 1 {
+            fields.insert(field, value);
+        }
+    }
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+fn is_ambiguous<T: Crud>(prefix: &str) -> Result<bool> {
+    let slugs = T::available_slugs(T::mapping()?);
+    Ok(slugs.iter().filter(|s| s.starts_with(prefix)).count() > 1)
+}
+
 pub fn add_subject<T>(obj: T) -> Result<()>
 where
     T: Crud,
 {
-    Ok(obj.add()?)
+    obj.add()
 }
 
 pub fn update_subject<T>(obj_slug: &str) -> Result<()>
 where
     T: Crud,
 {
-    let obj = T::retrieve(obj_slug)?;
+    let obj = T::retrieve_exact(obj_slug)?;
     let obj = obj.interactive_update();
-    Ok(obj.overwrite()?)
+    obj.overwrite()
 }
 
-pub fn delete_subject<'de, T>(obj_slug: &str) -> Result<()>
+pub fn delete_subject<T>(obj_slug: &str) -> Result<()>
 where
     T: Crud,
 {
-    let obj = T::retrieve(obj_slug)?;
-    Ok(obj.delete()?)
+    let obj = T::retrieve_exact(obj_slug)?;
+    obj.delete()
 }
 
-pub fn view_subject<'de, T>(obj_slug: Option<String>) -> Result<()>
+pub fn view_subject<T>(obj_slug: Option<String>) -> Result<()>
 where
     T: Crud,
     T: View,
+    T: Serialize + Columns,
 {
+    let output = output::current();
     match obj_slug {
+        Some(slug) if output.is_machine() => {
+            output::emit(&output.render_one(&T::retrieve(&slug)?)?)?
+        }
         Some(slug) => println!("{}", T::retrieve(&slug)?.format_detail()),
         None => {
             let items = T::mapping()?.values().cloned().collect::<Vec<T>>();
-            println!("{}", T::format_list(items))
+            print_list(items)?
         }
     };
     Ok(())
 }
 
-pub fn view_filtered_set<'de, T, F, S>(filters: Vec<F>, sort: S) -> Result<()>
+pub fn view_filtered_set<T, F, S>(filters: Vec<F>, sort: S) -> Result<()>
 where
     T: Filter<F, S>,
     F: Clone,
     T: View,
+    T: Serialize + Columns,
 {
     let items = T::get_base_items()?;
-    let items = T::apply_filterset(items, filters)?;
+    let items = match T::apply_filterset(items, filters) {
+        // scripts get an empty list rather than a warning
+        Err(CliError::FilterNoResults) if output::current().is_machine() => vec![],
+        items => items?,
+    };
     let items = T::sort(items, sort);
-    println!("{}", T::format_list(items));
+    print_list(items)
+}
+
+fn print_list<T>(items: Vec<T>) -> Result<()>
+where
+    T: View,
+    T: Serialize + Columns,
+{
+    match output::current() {
+        Output::Table => println!("{}", T::format_list(items)),
+        output => output::emit(&output.render_list(&items)?)?,
+    }
     Ok(())
 }
+
+/// Helpers for tests that read and write data files.
+#[cfg(test)]
+pub mod testing {
+    use crate::alias::Alias;
+    use crate::contractors::Contractor;
+    use crate::generics::Crud;
+    use crate::hours::HourLog;
+    use crate::invoice::Invoice;
+    use crate::output::Columns;
+    use serde::Serialize;
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::{env, fs, process};
+
+    const CONTRACTORS: &str = r#"
+[acme]
+slug = "acme"
+name = "Acme"
+currency = "EUR"
+
+[globex]
+slug = "globex"
+name = "Globex"
+currency = "USD"
+"#;
+
+    const ALIASES: &str = r#"
+[web]
+slug = "web"
+contractor = "acme"
+short_description = "Website"
+hourly_rate = "90.00 EUR"
+
+[[web.rate_changes]]
+effective_from = "2024-03-05"
+rate = "120.00 EUR"
+
+[api]
+slug = "api"
+contractor = "acme"
+short_description = "Backend API"
+hourly_rate = "100.00 EUR"
+
+[shop]
+slug = "shop"
+contractor = "globex"
+short_description = "Web shop"
+hourly_rate = "80.00 USD"
+"#;
+
+    const HOURS: &str = r#"{
+"w1": {"alias": "web", "minutes": 60, "date": "2024-03-04", "message": "Landing page", "ticket": "T-1", "branch": null, "id": "w1", "timestamp": "2024-03-04T10:00:00"},
+"w2": {"alias": "web", "minutes": 30, "date": "2024-03-05", "message": "Fixes", "ticket": "T-2", "branch": null, "id": "w2", "timestamp": "2024-03-05T10:00:00"},
+"a1": {"alias": "api", "minutes": 90, "date": "2024-03-05", "message": "Auth", "ticket": null, "branch": null, "id": "a1", "timestamp": "2024-03-05T11:00:00"},
+"s1": {"alias": "shop", "minutes": 120, "date": "2024-03-06", "message": "Checkout", "ticket": "S-1", "branch": null, "id": "s1", "timestamp": "2024-03-06T09:00:00"}
+}"#;
+
+    const INVOICES: &str = r#"
+[INV-2024-0001]
+number = "INV-2024-0001"
+sequence = 1
+contractor = "acme"
+period_start = "2024-03-01"
+period_end = "2024-03-31"
+issue_date = "2024-03-04"
+due_date = "2024-04-03"
+status = "sent"
+detail = "alias"
+total = ["90.00 EUR"]
+hours = ["w1"]
+
+[[INV-2024-0001.lines]]
+description = "Website"
+minutes = 60
+rate = "90.00 EUR"
+amount = "90.00 EUR"
+
+[INV-2024-0002]
+number = "INV-2024-0002"
+sequence = 2
+contractor = "globex"
+period_start = "2024-03-01"
+period_end = "2024-03-31"
+issue_date = "2024-03-06"
+due_date = "2024-04-05"
+status = "draft"
+detail = "alias"
+total = ["160.00 USD"]
+hours = ["s1"]
+
+[[INV-2024-0002.lines]]
+description = "Web shop"
+minutes = 120
+rate = "80.00 USD"
+amount = "160.00 USD"
+"#;
+
+    /// Two contractors: acme (EUR) with the aliases web (rate change on 2024-03-05) and
+    /// api, and globex (USD) with shop. Hour log w1 is on an acme invoice, s1 on a globex
+    /// draft; w2 and a1 are not billed.
+    pub fn sample() -> [(&'static str, &'static str); 4] {
+        [
+            (Contractor::FILE, CONTRACTORS),
+            (Alias::FILE, ALIASES),
+            (HourLog::FILE, HOURS),
+            (Invoice::FILE, INVOICES),
+        ]
+    }
+
+    /// `BOOKIT_DIR` is shared by the whole test process, tests using it run one at a time.
+    static DATA_DIR: Mutex<()> = Mutex::new(());
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Runs `test` with `BOOKIT_DIR` pointing at a new directory holding `files` (path and
+    /// content), which is removed afterwards.
+    pub fn with_data_dir<R>(files: &[(&str, &str)], test: impl FnOnce() -> R) -> R {
+        let _lock = DATA_DIR
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = env::temp_dir().join(format!(
+            "bookit-test-{}-{}",
+            process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        env::set_var("BOOKIT_DIR", &dir);
+        let result = panic::catch_unwind(AssertUnwindSafe(test));
+        let _ = fs::remove_dir_all(&dir);
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Asserts that `T::COLUMNS` are exactly the fields `items` serialize between them.
+    pub fn assert_columns<T: Serialize + Columns>(items: &[T]) {
+        let fields = items
+            .iter()
+            .flat_map(|item| match serde_json::to_value(item).unwrap() {
+                Value::Object(fields) => fields.keys().cloned().collect(),
+                _ => vec![],
+            })
+            .collect::<BTreeSet<String>>();
+        let columns = T::COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .collect::<BTreeSet<String>>();
+        assert_eq!(fields, columns);
+        assert_eq!(columns.len(), T::COLUMNS.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{sample, with_data_dir};
+    use super::*;
+    use crate::alias::Alias;
+    use crate::hours::HourLog;
+
+    #[test]
+    fn rereads_files_changed_within_the_same_modification_time() {
+        with_data_dir(&sample(), || {
+            assert!(Alias::mapping().unwrap().contains_key("shop"));
+            let path = Alias::path().unwrap();
+            let (modified, _) = stamp(&path).unwrap();
+            let content = fs::read_to_string(&path).unwrap();
+            let trimmed = &content[..content.find("[shop]").unwrap()];
+            fs::write(&path, trimmed).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+            assert_eq!(stamp(&path).unwrap().0, modified);
+            assert!(!Alias::mapping().unwrap().contains_key("shop"));
+        });
+    }
+
+    #[test]
+    fn failed_commits_restore_the_files_already_replaced() {
+        with_data_dir(&sample(), || {
+            let mut aliases = Alias::mapping().unwrap();
+            aliases.remove("shop");
+            let mut logs = HourLog::mapping().unwrap();
+            logs.remove("s1");
+            let staged = vec![
+                Alias::stage_map(aliases).unwrap(),
+                HourLog::stage_map(logs).unwrap(),
+            ];
+            // the second rename fails
+            fs::remove_file(&staged[1].temp).unwrap();
+            assert!(commit_staged(staged).is_err());
+            assert!(Alias::mapping().unwrap().contains_key("shop"));
+            assert!(HourLog::mapping().unwrap().contains_key("s1"));
+            let leftovers = fs::read_dir(data_dir().unwrap())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| name.ends_with(".staged") || name.ends_with(".backup"))
+                .collect::<Vec<String>>();
+            assert!(leftovers.is_empty(), "{:?}", leftovers);
+        });
+    }
+
+    #[test]
+    fn creates_private_temp_files() {
+        let path = private_temp_file("bookit-test", "toml", "a = 1\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a = 1\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let other = private_temp_file("bookit-test", "toml", "").unwrap();
+        assert_ne!(path, other);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(other).unwrap();
+    }
+}
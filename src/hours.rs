@@ -1,10 +1,14 @@
 use crate::alias::Alias;
 use crate::errors::CliError;
 use crate::generics::{
-    add_subject, delete_subject, view_filtered_set, view_subject, Crud, Filter, Result, View,
+    add_subject, confirm, delete_subject, retrieve_or_pick, view_filtered_set, view_subject, Crud,
+    Filter, Result, View,
 };
-use crate::utils::parse_date;
+use crate::invoice::Invoice;
+use crate::money::Money;
+use crate::output::{self, Columns};
 use crate::utils::parse_time;
+use crate::utils::{did_you_mean, parse_date, unknown_key};
 use chrono::{Local, NaiveDate, NaiveDateTime};
 use colored::*;
 use harsh::Harsh;
@@ -13,6 +17,7 @@ use serde_json::de::from_str as from_json;
 use serde_json::ser::to_string as to_json;
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::io::{self, Read};
 use std::str::FromStr;
 use structopt::StructOpt;
 
@@ -24,9 +29,13 @@ enum CmdError {
     InvalidHours(String),
     InvalidMinutes(String),
     InvalidTime(String),
+    MissingTime(String),
     InvalidFilterField(String),
     InvalidSort(String),
     InvalidFilterExpr(String),
+    InvalidBilled(String),
+    MissingField(String),
+    UnknownField(String),
 }
 
 impl From<CmdError> for CliError {
@@ -39,27 +48,42 @@ impl From<CmdError> for CliError {
                 "No time specified after directive (use '<s | t>::08:00'".to_string(),
             ),
             CmdError::NoHours => CliError::CmdError("No hours specified (use 'h:1.5')".to_string()),
-            CmdError::InvalidHours(h) => CliError::CmdError(format!(
-                "could not parse hours {} (use a float or integer)",
-                h.yellow().bold()
-            )),
-            CmdError::InvalidMinutes(m) => CliError::CmdError(format!(
-                "could not parse minutes {} (use an integer)",
-                m.yellow().bold()
+            CmdError::InvalidHours(h) => CliError::Parse {
+                input: h,
+                description: "hours should be a float or integer".into(),
+            },
+            CmdError::InvalidMinutes(m) => CliError::Parse {
+                input: m,
+                description: "minutes should be an integer".into(),
+            },
+            CmdError::InvalidTime(t) => CliError::Parse {
+                input: t,
+                description: "time should be <int> | h::<f64> | <s or t>::HH:MM".into(),
+            },
+            CmdError::MissingTime(alias) => CliError::CmdError(format!(
+                "no time given for {} (e.g. bookit hours book {} 30)",
+                alias.yellow().bold(),
+                alias
             )),
-            CmdError::InvalidTime(t) => {
-                CliError::CmdError(format!("unable to interpret time: {}", t.yellow().bold()))
-            }
-            CmdError::InvalidFilterField(f) => {
-                CliError::CmdError(format!("cannot filter on {}", f.yellow().bold()))
-            }
-            CmdError::InvalidSort(s) => {
-                CliError::CmdError(format!("cannot sort on {}", s.yellow().bold()))
-            }
+            CmdError::InvalidFilterField(f) => unknown_key("cannot filter on", &f, &FILTER_FIELDS),
+            CmdError::InvalidSort(s) => unknown_key("cannot sort on", &s, &SORT_KEYS),
             CmdError::InvalidFilterExpr(expr) => CliError::CmdError(format!(
                 "unable to interpret filter expression {} (use <field>::<value>)",
                 expr.yellow().bold()
             )),
+            CmdError::InvalidBilled(filter) => CliError::CmdError(format!(
+                "cannot filter on {}{}",
+                filter.yellow().bold(),
+                did_you_mean(&["billed::yes".into(), "billed::no".into()])
+            )),
+            CmdError::MissingField(field) => {
+                CliError::CmdError(format!("{} is required", field.yellow().bold()))
+            }
+            CmdError::UnknownField(field) => CliError::CmdError(format!(
+                "unknown field {} (use {})",
+                field.yellow().bold(),
+                BOOKING_FIELDS.join(", ")
+            )),
         }
     }
 }
@@ -76,12 +100,19 @@ pub struct HourLog {
     pub timestamp: NaiveDateTime,
 }
 
+/// Fields of a booking read from stdin, in the column order of CSV rows without a header.
+const BOOKING_FIELDS: [&str; 6] = ["alias", "time", "date", "message", "ticket", "branch"];
+
+type Fields = HashMap<String, String>;
+
 #[derive(StructOpt, Debug, Clone)]
 pub struct CreateArgs {
-    alias: Alias,
+    /// Alias slug or prefix; when omitted (or ambiguous) it is picked from a list in a terminal
+    #[structopt(name = "alias", required_unless = "stdin")]
+    alias: Option<String>,
     /// Time in minutes or a stretch pattern (e.g. <int> | h::<f64> | <s or t>::HH:MM | s::last)
-    #[structopt(name="time", parse(try_from_str = interpret_time))]
-    time: u32,
+    #[structopt(name = "time")]
+    time: Option<String>,
     /// Date in isoformat or weekday (e.g. "YYYY-MM-DD" | <weekday>)
     #[structopt(short = "d", long = "date", default_value = "today", parse(try_from_str = parse_date))]
     date: NaiveDate,
@@ -94,6 +125,9 @@ pub struct CreateArgs {
     /// Reference to git branch for work (e.g. "feature/RAS-002")
     #[structopt(short = "b", long = "branch")]
     branch: Option<String>,
+    /// Read bookings from stdin, as JSON lines or CSV rows (alias, time, date, message, ticket, branch)
+    #[structopt(long = "stdin", conflicts_with_all = &["alias", "time", "date", "message", "ticket", "branch"])]
+    stdin: bool,
 }
 
 #[derive(StructOpt, Debug)]
@@ -109,15 +143,61 @@ pub enum Cmd {
         #[structopt(short = "s", default_value = "no_sort")]
         sort: S,
     },
-    /// Delete an hour booking by hash
+    /// Delete an hour booking by hash, or every booking matching --where
     #[structopt(name = "delete")]
-    Delete { slug: String },
+    Delete {
+        #[structopt(required_unless = "filters")]
+        slug: Option<String>,
+        /// Filter directives selecting the bookings to delete (e.g. alias::acme billed::no)
+        #[structopt(long = "where", conflicts_with = "slug")]
+        filters: Vec<F>,
+        /// Delete the selected bookings without asking
+        #[structopt(short = "y", long = "yes")]
+        yes: bool,
+        /// Also delete bookings that are on an invoice
+        #[structopt(long = "include-billed")]
+        include_billed: bool,
+    },
+    /// Change the ticket or alias of every booking matching --where
+    #[structopt(name = "set")]
+    Set {
+        /// Filter directives selecting the bookings to change (e.g. alias::acme billed::no)
+        #[structopt(long = "where", required = true)]
+        filters: Vec<F>,
+        /// New ticket, `-` removes it
+        #[structopt(long = "ticket")]
+        ticket: Option<String>,
+        /// New alias
+        #[structopt(long = "alias")]
+        alias: Option<String>,
+        /// Change the selected bookings without asking
+        #[structopt(short = "y", long = "yes")]
+        yes: bool,
+        /// Also change bookings that are on an invoice
+        #[structopt(long = "include-billed")]
+        include_billed: bool,
+    },
     /// Add an hour booking
     #[structopt(name = "book")]
     Create(CreateArgs),
 }
 
-fn interpret_time(time_str: &str) -> Result<u32> {
+/// Error for changes to bookings that are on an invoice without `--include-billed`;
+/// `subject` names them (e.g. "3 of the hour logs are").
+pub fn billed_error(subject: String) -> CliError {
+    CliError::CmdError(format!(
+        "{} on an invoice, void it first or add --include-billed",
+        subject
+    ))
+}
+
+/// Whether `input` is meant as a time (minutes or a pattern) rather than an alias.
+fn looks_like_time(input: &str) -> bool {
+    input.contains("::") || input.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Minutes of a time argument (e.g. `30`, `h::1.5`, `s::09:00`).
+pub fn interpret_time(time_str: &str) -> Result<u32> {
     let res = match time_str {
         time_str if time_str.starts_with("h::") => {
             if let Some(maybe_h) = time_str.get(3..) {
@@ -163,12 +243,35 @@ fn interpret_time(time_str: &str) -> Result<u32> {
 impl Cmd {
     pub fn exec(&self) -> Result<()> {
         match self {
-            Self::Delete { slug } => delete_subject::<HourLog>(&slug)?,
+            Self::Delete {
+                slug: Some(slug),
+                include_billed,
+                ..
+            } => {
+                if !include_billed && Invoice::billed_hours()?.contains(slug) {
+                    return Err(billed_error(format!("{} is", slug.yellow().bold())));
+                }
+                delete_subject::<HourLog>(slug)?
+            }
+            Self::Delete {
+                filters,
+                yes,
+                include_billed,
+                ..
+            } => HourLog::delete_where(filters.to_vec(), *yes, *include_billed)?,
+            Self::Set {
+                filters,
+                ticket,
+                alias,
+                yes,
+                include_billed,
+            } => HourLog::set_where(filters.to_vec(), ticket, alias, *yes, *include_billed)?,
             Self::Detail { slug } => view_subject::<HourLog>(Some(slug.to_owned()))?,
             Self::Show { filters, sort } => {
                 let sort = sort.clone();
                 view_filtered_set::<HourLog, F, S>(filters.to_vec(), sort)?
             }
+            Self::Create(args) if args.stdin => HourLog::book_stdin()?,
             Self::Create(args) => add_subject::<HourLog>(HourLog::try_from(args.clone())?)?,
         };
         Ok(())
@@ -180,23 +283,294 @@ impl TryFrom<CreateArgs> for HourLog {
 
     fn try_from(args: CreateArgs) -> Result<Self> {
         let now = Local::now().naive_local();
+        let existing = Self::mapping()?;
+        let hash = Self::generate_id(now, |id| existing.contains_key(id))?;
+        Self::from_args(args, hash, now)
+    }
+}
+
+impl CreateArgs {
+    /// Booking from named fields, parsed the same way as the command line arguments.
+    fn from_fields(mut fields: Fields) -> Result<Self> {
+        if let Some(field) = fields
+            .keys()
+            .find(|f| !BOOKING_FIELDS.contains(&f.as_str()))
+        {
+            return Err(CmdError::UnknownField(field.clone()).into());
+        }
+        let mut required = |field: &str| {
+            fields
+                .remove(field)
+                .ok_or_else(|| CmdError::MissingField(field.to_string()))
+        };
+        let alias = required("alias")?;
+        let time = required("time")?;
+        let date = parse_date(&fields.remove("date").unwrap_or_else(|| "today".into()))?;
+        Ok(Self {
+            alias: Some(alias),
+            time: Some(time),
+            date,
+            message: fields.remove("message"),
+            ticket: fields.remove("ticket"),
+            branch: fields.remove("branch"),
+            stdin: false,
+        })
+    }
+
+    /// Alias and minutes of the booking. With a single argument that argument is the time
+    /// and the alias is picked, as it is for an ambiguous alias prefix; a single argument
+    /// that does not look like a time is taken as an alias without a time.
+    fn resolve(&self) -> Result<(Alias, u32)> {
+        let (alias, time) = match (&self.alias, &self.time) {
+            (Some(alias), Some(time)) => (Some(alias.as_str()), time),
+            (Some(time), None) if looks_like_time(time) => (None, time),
+            (Some(alias), None) => return Err(CmdError::MissingTime(alias.clone()).into()),
+            (None, _) => return Err(CmdError::MissingField("time".into()).into()),
+        };
+        let minutes = interpret_time(time)?;
+        Ok((retrieve_or_pick::<Alias>(alias, "Alias")?, minutes))
+    }
+}
+
+/// Rows of JSON lines or CSV (with or without a header) as fields by name, keyed by line
+/// number. Empty values are left out.
+fn booking_rows(input: &str) -> Result<Vec<(usize, Result<Fields>)>> {
+    let first = input.lines().map(str::trim).find(|line| !line.is_empty());
+    if let Some(true) = first.map(|line| line.starts_with('{')) {
+        let rows = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let fields = from_json::<serde_json::Map<String, serde_json::Value>>(line)
+                    .map(|map| {
+                        map.into_iter()
+                            .filter_map(|(field, value)| match value {
+                                serde_json::Value::Null => None,
+                                serde_json::Value::String(s) if s.is_empty() => None,
+                                serde_json::Value::String(s) => Some((field, s)),
+                                value => Some((field, value.to_string())),
+                            })
+                            .collect()
+                    })
+                    .map_err(CliError::from);
+                (i + 1, fields)
+            })
+            .collect();
+        return Ok(rows);
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes());
+    let mut names = BOOKING_FIELDS
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<String>>();
+    let mut rows = vec![];
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let line = record.position().map_or(i + 1, |p| p.line() as usize);
+        if i == 0 && record.get(0) == Some("alias") {
+            names = record.iter().map(String::from).collect();
+            continue;
+        }
+        let fields = if record.len() > names.len() {
+            Err(CliError::CmdError(format!(
+                "expected at most {} values ({})",
+                names.len(),
+                names.join(", ")
+            )))
+        } else {
+            Ok(names
+                .iter()
+                .cloned()
+                .zip(record.iter().map(String::from))
+                .filter(|(_, value)| !value.is_empty())
+                .collect())
+        };
+        rows.push((line, fields));
+    }
+    Ok(rows)
+}
+
+impl HourLog {
+    /// Hash of the creation time; bookings created within the same second get a counter
+    /// added so `taken` ids are never reused.
+    pub fn generate_id(now: NaiveDateTime, taken: impl Fn(&str) -> bool) -> Result<String> {
         let encoder = Harsh::builder()
             .salt("bookit")
             .build()
             .or(Err(CmdError::Hasher))?;
-        let hash = encoder.encode(&[now.timestamp() as u64]).to_lowercase();
-        let hours = Self {
-            alias: args.alias.slug,
-            minutes: args.time,
+        let mut hash = encoder.encode(&[now.timestamp() as u64]).to_lowercase();
+        let mut counter = 0;
+        while taken(&hash) {
+            counter += 1;
+            hash = encoder
+                .encode(&[now.timestamp() as u64, counter])
+                .to_lowercase();
+        }
+        Ok(hash)
+    }
+
+    fn from_args(args: CreateArgs, id: String, timestamp: NaiveDateTime) -> Result<Self> {
+        let (alias, minutes) = args.resolve()?;
+        Ok(Self {
+            alias: alias.slug,
+            minutes,
             date: args.date,
             message: args.message,
             ticket: args.ticket,
             branch: args.branch,
-            id: hash,
-            timestamp: now,
-        };
-        Ok(hours)
+            id,
+            timestamp,
+        })
+    }
+
+    /// Books every row on stdin in a single write; nothing is written when any row is invalid.
+    fn book_stdin() -> Result<()> {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(CliError::Read)?;
+        let now = Local::now().naive_local();
+        let mut mapping = Self::mapping()?;
+        let mut errors = vec![];
+        let mut booked = 0;
+        for (line, fields) in booking_rows(&input)? {
+            let log = fields.and_then(CreateArgs::from_fields).and_then(|args| {
+                let id = Self::generate_id(now, |id| mapping.contains_key(id))?;
+                let log = Self::from_args(args, id, now)?;
+                log.validate()?;
+                Ok(log)
+            });
+            match log {
+                Ok(log) => {
+                    mapping.insert(log.id.clone(), log);
+                    booked += 1;
+                }
+                Err(err) => errors.push(format!("line {}: {}", line, err.message())),
+            }
+        }
+        if !errors.is_empty() {
+            output::note(errors.join("\n"));
+            return Err(CliError::CmdError(format!(
+                "{} rows could not be booked, nothing was written",
+                errors.len()
+            )));
+        }
+        Self::commit_map(mapping)?;
+        output::note(format!("Booked {} hour logs", booked));
+        Ok(())
+    }
+
+    /// The bookings matching `filters`, newest first, after listing them with `action` and a
+    /// warning when some of them are already on an invoice.
+    fn preview(filters: Vec<F>, action: &str, include_billed: bool) -> Result<Vec<Self>> {
+        let selected = Self::apply_filterset(Self::get_base_items()?, filters)?;
+        if selected.is_empty() {
+            return Err(CliError::FilterNoResults);
+        }
+        let selected = Self::sort(selected, S::ByTimestamp);
+        let billed = Invoice::billed_hours()?;
+        let on_invoices = selected
+            .iter()
+            .filter(|log| billed.contains(&log.id))
+            .count();
+        output::note(Self::format_list(selected.clone()));
+        if on_invoices > 0 && !include_billed {
+            return Err(billed_error(format!(
+                "{} of the hour logs are",
+                on_invoices.to_string().yellow().bold()
+            )));
+        }
+        output::note(format!("{} hour logs will be {}", selected.len(), action));
+        if on_invoices > 0 {
+            output::note(format!(
+                "{} of them are billed, their invoices keep the lines they were created with",
+                on_invoices.to_string().yellow()
+            ));
+        }
+        Ok(selected)
+    }
+
+    /// Deletes every booking matching `filters` in a single write.
+    fn delete_where(filters: Vec<F>, yes: bool, include_billed: bool) -> Result<()> {
+        let selected = Self::preview(filters, "deleted", include_billed)?;
+        if !confirm("Delete them?", yes)? {
+            return Err(CliError::CmdError("nothing was deleted".into()));
+        }
+        let mut mapping = Self::mapping()?;
+        for log in &selected {
+            mapping.remove(&log.id);
+        }
+        Self::commit_map(mapping)?;
+        output::note(format!("Deleted {} hour logs", selected.len()));
+        Ok(())
+    }
+
+    /// Sets the ticket and/or alias of every booking matching `filters` in a single write.
+    fn set_where(
+        filters: Vec<F>,
+        ticket: &Option<String>,
+        alias: &Option<String>,
+        yes: bool,
+        include_billed: bool,
+    ) -> Result<()> {
+        let alias = alias.as_deref().map(Alias::retrieve_exact).transpose()?;
+        let mut changes = vec![];
+        if let Some(ticket) = ticket {
+            changes.push(match ticket.as_str() {
+                "-" => "stripped of their ticket".to_string(),
+                ticket => format!("given ticket {}", ticket.bold()),
+            });
+        }
+        if let Some(alias) = &alias {
+            changes.push(format!("moved to {}", alias.slug.purple().bold()));
+        }
+        if changes.is_empty() {
+            return Err(CliError::CmdError(
+                "nothing to change, add --ticket or --alias".into(),
+            ));
+        }
+        let selected = Self::preview(filters, &changes.join(" and "), include_billed)?;
+        if !confirm("Change them?", yes)? {
+            return Err(CliError::CmdError("nothing was changed".into()));
+        }
+        let mut mapping = Self::mapping()?;
+        for mut log in selected.iter().cloned() {
+            if let Some(ticket) = ticket {
+                log.ticket = Some(ticket.clone()).filter(|ticket| ticket != "-");
+            }
+            if let Some(alias) = &alias {
+                log.alias = alias.slug.clone();
+            }
+            log.validate()?;
+            mapping.insert(log.id.clone(), log);
+        }
+        Self::commit_map(mapping)?;
+        output::note(format!("Changed {} hour logs", selected.len()));
+        Ok(())
     }
+
+    /// Billable value of the booking at the alias rate valid on the booking date.
+    pub fn amount(&self, alias: &Alias) -> Money {
+        alias.rate_on(self.date).for_minutes(self.minutes)
+    }
+}
+
+impl Columns for HourLog {
+    const COLUMNS: &'static [&'static str] = &[
+        "alias",
+        "minutes",
+        "date",
+        "message",
+        "ticket",
+        "branch",
+        "id",
+        "timestamp",
+    ];
 }
 
 impl View for HourLog {
@@ -237,15 +611,24 @@ impl Crud for HourLog {
         Ok(to_json(&map)?)
     }
 
+    fn validate(&self) -> Result<()> {
+        Alias::write_ok(&self.alias, true)
+    }
+
     fn interactive_update(&self) -> Self {
         self.clone()
     }
 }
 
+pub const FILTER_FIELDS: [&str; 2] = ["alias", "billed"];
+pub const SORT_KEYS: [&str; 3] = ["no_sort", "ts", "timestamp"];
+
 #[derive(Clone, Debug)]
 pub enum F {
     NoFilter,
     ByAlias(String),
+    /// Hour logs that are (or are not) on an invoice that was not voided
+    Billed(bool),
 }
 
 impl FromStr for F {
@@ -258,6 +641,11 @@ impl FromStr for F {
                 Some(alias) => Ok(Self::ByAlias(alias.into())),
                 None => Err(CmdError::InvalidFilterExpr(input.to_owned()).into()),
             },
+            "billed::yes" => Ok(Self::Billed(true)),
+            "billed::no" => Ok(Self::Billed(false)),
+            input if input.starts_with("billed::") => {
+                Err(CmdError::InvalidBilled(input.to_owned()).into())
+            }
             input if input.contains("::") => match input.find("::").unwrap() {
                 0 => Err(CmdError::InvalidFilterExpr(input.to_owned()).into()),
                 x => {
@@ -297,25 +685,148 @@ impl Filter<F, S> for HourLog {
         Ok(mapping.values().cloned().collect::<Vec<Self>>())
     }
 
-    fn filter(items: Vec<Self>, method: F) -> Vec<Self> {
-        match method {
+    fn filter(items: Vec<Self>, method: F) -> Result<Vec<Self>> {
+        Ok(match method {
             F::NoFilter => items,
             F::ByAlias(alias) => items
                 .into_iter()
                 .filter(|item| item.alias == alias)
                 .collect(),
-        }
+            F::Billed(expect) => {
+                let billed = Invoice::billed_hours()?;
+                items
+                    .into_iter()
+                    .filter(|item| billed.contains(&item.id) == expect)
+                    .collect()
+            }
+        })
     }
 
     fn sort(items: Vec<Self>, method: S) -> Vec<Self> {
-        let items = match method {
+        match method {
             S::ByTimestamp => {
                 let mut items = items;
-                items.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+                items.sort_by_key(|item| std::cmp::Reverse(item.timestamp));
                 items
             }
             S::NoSort => items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+    use crate::utils::interactive;
+
+    fn filters(directives: &[&str]) -> Vec<F> {
+        directives
+            .iter()
+            .map(|directive| F::from_str(directive).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn deletes_only_the_selected_bookings() {
+        with_data_dir(&sample(), || {
+            HourLog::delete_where(filters(&["alias::web", "billed::no"]), true, false).unwrap();
+            let logs = HourLog::mapping().unwrap();
+            let mut ids = logs.keys().map(String::as_str).collect::<Vec<&str>>();
+            ids.sort();
+            assert_eq!(ids, vec!["a1", "s1", "w1"]);
+        });
+    }
+
+    #[test]
+    fn times_that_do_not_parse_are_parse_errors() {
+        let resolve = |args: &[&str]| {
+            let args = std::iter::once("book").chain(args.iter().copied());
+            CreateArgs::from_iter_safe(args)
+                .unwrap()
+                .resolve()
+                .map(|(alias, minutes)| (alias.slug, minutes))
         };
-        items
+        with_data_dir(&sample(), || {
+            assert_eq!(resolve(&["web", "h::1.5"]).unwrap(), ("web".into(), 90));
+            assert_eq!(resolve(&["web", "ten"]).unwrap_err().exit_code(), 3);
+            assert_eq!(resolve(&["web", "h::x"]).unwrap_err().exit_code(), 3);
+            assert_eq!(resolve(&["web", "x::1"]).unwrap_err().exit_code(), 3);
+            // a single word is an alias without a time, not a time that does not parse
+            let missing = resolve(&["web"]).unwrap_err();
+            assert_eq!(missing.exit_code(), 2);
+            assert!(missing.to_string().contains("no time given"), "{}", missing);
+            assert_eq!(resolve(&["1o"]).unwrap_err().exit_code(), 3);
+        });
+    }
+
+    #[test]
+    fn billed_filters_read_the_invoices_when_applied() {
+        let unknown = F::from_str("billed::maybe").unwrap_err().to_string();
+        assert!(unknown.contains("billed::yes | billed::no"), "{}", unknown);
+        // parsing does not read the data, so filters can be made before the data exists
+        let unbilled = filters(&["billed::no"]);
+        with_data_dir(&sample(), || {
+            let logs = HourLog::apply_filterset(HourLog::get_base_items().unwrap(), unbilled);
+            let mut ids = logs
+                .unwrap()
+                .into_iter()
+                .map(|log| log.id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            assert_eq!(ids, vec!["a1", "w2"]);
+        });
+    }
+
+    #[test]
+    fn billed_bookings_need_include_billed() {
+        with_data_dir(&sample(), || {
+            // w1 is on a sent invoice
+            assert!(HourLog::delete_where(filters(&["alias::web"]), true, false).is_err());
+            let ticket = Some("T-9".to_string());
+            assert!(
+                HourLog::set_where(filters(&["alias::web"]), &ticket, &None, true, false).is_err()
+            );
+            assert_eq!(HourLog::mapping().unwrap().len(), 4);
+            HourLog::set_where(filters(&["alias::web"]), &ticket, &None, true, true).unwrap();
+            assert_eq!(
+                HourLog::mapping().unwrap()["w1"].ticket.as_deref(),
+                Some("T-9")
+            );
+            HourLog::delete_where(filters(&["alias::web"]), true, true).unwrap();
+            assert_eq!(HourLog::mapping().unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn a_dash_removes_the_ticket() {
+        with_data_dir(&sample(), || {
+            let strip = Some("-".to_string());
+            let alias = Some("api".to_string());
+            HourLog::set_where(
+                filters(&["alias::web", "billed::no"]),
+                &strip,
+                &alias,
+                true,
+                false,
+            )
+            .unwrap();
+            let w2 = &HourLog::mapping().unwrap()["w2"];
+            assert_eq!(w2.ticket, None);
+            assert_eq!(w2.alias, "api");
+        });
+    }
+
+    #[test]
+    fn bulk_changes_need_yes_without_a_terminal() {
+        // with a terminal on stdin the question would be asked instead
+        if interactive() {
+            return;
+        }
+        with_data_dir(&sample(), || {
+            let err = HourLog::delete_where(filters(&["billed::no"]), false, false).unwrap_err();
+            assert!(err.to_string().contains("--yes"));
+            assert_eq!(HourLog::mapping().unwrap().len(), 4);
+        });
     }
 }
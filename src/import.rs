@@ -0,0 +1,526 @@
+//! Import of the detailed CSV exports of other time trackers. Clients become contractors and
+//! projects become aliases (matched by slug, created when missing); every time entry becomes
+//! an hour log unless an identical booking already exists.
+use crate::alias::Alias;
+use crate::contractors::Contractor;
+use crate::errors::CliError;
+use crate::generics::{commit_staged, Crud, Result, View};
+use crate::hours::HourLog;
+use crate::invoice::format_hours;
+use crate::money::{Currency, Money};
+use crate::output;
+use crate::utils::slugify;
+use chrono::{NaiveDate, NaiveTime};
+use colored::*;
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Time tracker the file was exported from ( toggl | clockify | harvest )
+    source: Source,
+    /// Detailed time report in CSV format
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+    /// Contractor for entries without a client
+    #[structopt(short = "c", long = "contractor")]
+    contractor: Option<String>,
+    /// Show what would be imported without writing anything
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Toggl,
+    Clockify,
+    Harvest,
+}
+
+impl FromStr for Source {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "toggl" => Ok(Self::Toggl),
+            "clockify" => Ok(Self::Clockify),
+            "harvest" => Ok(Self::Harvest),
+            _ => Err(CliError::Parse {
+                input: input.into(),
+                description: "source should be one of ( toggl | clockify | harvest )".into(),
+            }),
+        }
+    }
+}
+
+/// A time entry as found in an export, before it is matched to bookit records.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    client: String,
+    project: String,
+    description: Option<String>,
+    date: NaiveDate,
+    start: Option<NaiveTime>,
+    minutes: u32,
+    rate: Option<Money>,
+}
+
+/// Header positions by lowercase column name.
+struct Columns(HashMap<String, usize>);
+
+impl Columns {
+    fn new(headers: &StringRecord) -> Self {
+        let columns = headers
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.trim_start_matches('\u{feff}').to_lowercase(), i))
+            .collect();
+        Self(columns)
+    }
+
+    /// Value of the first of `names` that is present and not empty.
+    fn get<'r>(&self, record: &'r StringRecord, names: &[&str]) -> Option<&'r str> {
+        names
+            .iter()
+            .filter_map(|name| self.0.get(*name))
+            .filter_map(|i| record.get(*i))
+            .find(|value| !value.is_empty())
+    }
+
+    fn require<'r>(&self, record: &'r StringRecord, names: &[&str]) -> Result<&'r str> {
+        self.get(record, names)
+            .ok_or_else(|| CliError::CmdError(format!("missing value for {}", names.join(" / "))))
+    }
+
+    /// Name of the first column starting with `prefix` (e.g. "billable rate (usd)").
+    fn find(&self, prefix: &str) -> Option<&str> {
+        let mut names = self
+            .0
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .collect::<Vec<&String>>();
+        names.sort();
+        names.first().map(|name| name.as_str())
+    }
+}
+
+impl Source {
+    fn entry(self, columns: &Columns, record: &StringRecord) -> Result<Entry> {
+        let client = columns.get(record, &["client"]).unwrap_or_default();
+        let project = columns.require(record, &["project"])?;
+        let (description, date, minutes) = match self {
+            Self::Toggl => (
+                columns.get(record, &["description"]),
+                columns.require(record, &["start date"])?,
+                parse_duration(columns.require(record, &["duration"])?)?,
+            ),
+            Self::Clockify => (
+                columns.get(record, &["description"]),
+                columns.require(record, &["start date"])?,
+                match columns.get(record, &["duration (decimal)"]) {
+                    Some(hours) => parse_hours(hours)?,
+                    None => parse_duration(columns.require(record, &["duration (h)"])?)?,
+                },
+            ),
+            Self::Harvest => (
+                columns.get(record, &["notes"]),
+                columns.require(record, &["date"])?,
+                parse_hours(columns.require(record, &["hours"])?)?,
+            ),
+        };
+        let start = match columns.get(record, &["start time"]) {
+            Some(time) => Some(parse_clock(time)?),
+            None => None,
+        };
+        Ok(Entry {
+            client: client.to_string(),
+            project: project.to_string(),
+            description: description.map(String::from),
+            date: parse_export_date(date)?,
+            start,
+            minutes,
+            rate: self.rate(columns, record)?,
+        })
+    }
+
+    /// Hourly rate of the entry when the export has one. Clockify puts the currency in the
+    /// column name ("Billable Rate (USD)"), Harvest has a currency column ("Euro - EUR").
+    fn rate(self, columns: &Columns, record: &StringRecord) -> Result<Option<Money>> {
+        let (amount, currency) = match self {
+            Self::Toggl => return Ok(None),
+            Self::Clockify => match columns.find("billable rate (") {
+                Some(column) => (
+                    columns.get(record, &[column]),
+                    column
+                        .trim_start_matches("billable rate (")
+                        .trim_end_matches(')'),
+                ),
+                None => return Ok(None),
+            },
+            Self::Harvest => (
+                columns.get(record, &["billable rate"]),
+                columns
+                    .get(record, &["currency"])
+                    .and_then(|currency| currency.rsplit(" - ").next())
+                    .unwrap_or_default(),
+            ),
+        };
+        match (amount, Currency::from_str(&currency.to_uppercase())) {
+            (Some(amount), Ok(currency)) => {
+                let amount = round_to_minor(amount, &currency).unwrap_or_else(|| amount.into());
+                match Money::parse(&amount, &currency)? {
+                    rate if rate.minor == 0 => Ok(None),
+                    rate => Ok(Some(rate)),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Exports write rates with as many decimals as they like (e.g. "12000.0" for JPY); they are
+/// rounded to the minor unit of the currency before they are parsed as money.
+fn round_to_minor(amount: &str, currency: &Currency) -> Option<String> {
+    let amount = amount.trim().parse::<f64>().ok()?;
+    Some(format!("{:.*}", currency.exponent() as usize, amount))
+}
+
+fn parse_error(input: &str, description: &str) -> CliError {
+    CliError::Parse {
+        input: input.into(),
+        description: description.into(),
+    }
+}
+
+/// Minutes of a "HH:MM:SS" duration, rounded to the nearest minute.
+fn parse_duration(input: &str) -> Result<u32> {
+    let parts = input
+        .split(':')
+        .map(|part| part.trim().parse::<u32>())
+        .collect::<std::result::Result<Vec<u32>, _>>()
+        .map_err(|_| parse_error(input, "duration should be HH:MM:SS"))?;
+    match parts.as_slice() {
+        [h, m, s] => Ok(h * 60 + m + (s + 30) / 60),
+        [h, m] => Ok(h * 60 + m),
+        _ => Err(parse_error(input, "duration should be HH:MM:SS")),
+    }
+}
+
+/// Minutes of a decimal number of hours (e.g. "1.25").
+fn parse_hours(input: &str) -> Result<u32> {
+    match input.trim().parse::<f64>() {
+        Ok(hours) if hours >= 0.0 => Ok((hours * 60.0).round() as u32),
+        _ => Err(parse_error(input, "hours should be a decimal number")),
+    }
+}
+
+fn parse_export_date(input: &str) -> Result<NaiveDate> {
+    ["%Y-%m-%d", "%m/%d/%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(input.trim(), format).ok())
+        .ok_or_else(|| parse_error(input, "date should be YYYY-MM-DD or MM/DD/YYYY"))
+}
+
+fn parse_clock(input: &str) -> Result<NaiveTime> {
+    ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(input.trim(), format).ok())
+        .ok_or_else(|| parse_error(input, "time should be HH:MM:SS"))
+}
+
+/// Records the import would write, in addition to what is already stored.
+struct Plan {
+    contractors: HashMap<String, Contractor>,
+    aliases: HashMap<String, Alias>,
+    hours: HashMap<String, HourLog>,
+    /// Hour logs from before the import; only these count as duplicates, so identical rows
+    /// of one export (e.g. two equal sessions on a day) are all imported
+    existing: Vec<HourLog>,
+    new_contractors: Vec<String>,
+    new_aliases: Vec<String>,
+    new_hours: Vec<HourLog>,
+    duplicates: usize,
+}
+
+impl Plan {
+    fn new() -> Result<Self> {
+        let hours = HourLog::mapping()?;
+        Ok(Self {
+            contractors: Contractor::mapping()?,
+            aliases: Alias::mapping()?,
+            existing: hours.values().cloned().collect(),
+            hours,
+            new_contractors: vec![],
+            new_aliases: vec![],
+            new_hours: vec![],
+            duplicates: 0,
+        })
+    }
+
+    /// Slug of the contractor for the client of `entry`, matched by name or slug.
+    fn contractor(&mut self, entry: &Entry, fallback: &Option<String>) -> Result<String> {
+        let by_name = self
+            .contractors
+            .values()
+            .find(|contractor| contractor.name.eq_ignore_ascii_case(&entry.client));
+        if let (Some(contractor), false) = (by_name, entry.client.is_empty()) {
+            return Ok(contractor.slug.clone());
+        }
+        let slug = match (slugify(entry.client.clone()), fallback) {
+            (slug, _) if !slug.is_empty() => slug,
+            (_, Some(fallback)) => fallback.clone(),
+            (_, None) => {
+                return Err(CliError::CmdError(
+                    "entry has no client, use --contractor to assign one".into(),
+                ))
+            }
+        };
+        if !self.contractors.contains_key(&slug) {
+            if entry.client.is_empty() {
+                return Err(CliError::CmdError(format!(
+                    "contractor {} does not exist",
+                    slug
+                )));
+            }
+            let mut contractor = Contractor::blank(slug.clone(), entry.client.clone());
+            if let Some(rate) = &entry.rate {
+                contractor.currency = rate.currency.clone();
+            }
+            self.contractors.insert(slug.clone(), contractor);
+            self.new_contractors.push(slug.clone());
+        }
+        Ok(slug)
+    }
+
+    fn alias(&mut self, entry: &Entry, contractor: &str) -> Result<String> {
+        let slug = slugify(entry.project.clone());
+        match self.aliases.get(&slug) {
+            Some(alias) if alias.contractor != contractor => Err(CliError::CmdError(format!(
+                "project {} matches alias {} of contractor {}",
+                entry.project, slug, alias.contractor
+            ))),
+            Some(_) => Ok(slug),
+            None => {
+                let currency = &self.contractors[contractor].currency;
+                let alias = Alias {
+                    slug: slug.clone(),
+                    contractor: contractor.to_string(),
+                    short_description: entry.project.clone(),
+                    hourly_rate: entry
+                        .rate
+                        .clone()
+                        .unwrap_or_else(|| Money::zero(currency.clone())),
+                    rate_changes: vec![],
+                };
+                self.aliases.insert(slug.clone(), alias);
+                self.new_aliases.push(slug.clone());
+                Ok(slug)
+            }
+        }
+    }
+
+    fn add(&mut self, entry: Entry, fallback: &Option<String>) -> Result<()> {
+        let contractor = self.contractor(&entry, fallback)?;
+        let alias = self.alias(&entry, &contractor)?;
+        let duplicate = self.existing.iter().any(|log| {
+            log.alias == alias
+                && log.date == entry.date
+                && log.minutes == entry.minutes
+                && log.message == entry.description
+        });
+        if duplicate {
+            self.duplicates += 1;
+            return Ok(());
+        }
+        let timestamp = entry
+            .date
+            .and_time(entry.start.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0)));
+        let id = HourLog::generate_id(timestamp, |id| self.hours.contains_key(id))?;
+        let log = HourLog {
+            alias,
+            minutes: entry.minutes,
+            date: entry.date,
+            message: entry.description,
+            ticket: None,
+            branch: None,
+            id: id.clone(),
+            timestamp,
+        };
+        self.hours.insert(id, log.clone());
+        self.new_hours.push(log);
+        Ok(())
+    }
+
+    fn format_preview(&self) -> String {
+        let mut lines = vec![];
+        for slug in &self.new_contractors {
+            let contractor = &self.contractors[slug];
+            lines.push(format!(
+                "{} contractor {} ({})",
+                "+".green().bold(),
+                slug.bold(),
+                contractor.name
+            ));
+        }
+        for slug in &self.new_aliases {
+            let alias = &self.aliases[slug];
+            lines.push(format!(
+                "{} alias {} for {} at {}",
+                "+".green().bold(),
+                slug.bold(),
+                alias.contractor,
+                alias.hourly_rate
+            ));
+        }
+        let minutes = self.new_hours.iter().map(|log| log.minutes).sum();
+        lines.push(format!(
+            "{} {} hour logs ({} hours), {} duplicates skipped",
+            "+".green().bold(),
+            self.new_hours.len(),
+            format_hours(minutes),
+            self.duplicates
+        ));
+        lines.join("\n")
+    }
+
+    /// Writes the contractors, aliases and hours together, so a failed write leaves none of
+    /// them changed.
+    fn commit(self) -> Result<()> {
+        let mut staged = vec![];
+        if !self.new_contractors.is_empty() {
+            staged.push(Contractor::stage_map(self.contractors)?);
+        }
+        if !self.new_aliases.is_empty() {
+            staged.push(Alias::stage_map(self.aliases)?);
+        }
+        if !self.new_hours.is_empty() {
+            staged.push(HourLog::stage_map(self.hours)?);
+        }
+        commit_staged(staged)
+    }
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        let content = fs::read_to_string(&self.file).map_err(CliError::Read)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+        let columns = Columns::new(reader.headers()?);
+        let mut plan = Plan::new()?;
+        let mut errors = vec![];
+        for (i, record) in reader.records().enumerate() {
+            let result = record
+                .map_err(CliError::from)
+                .and_then(|record| self.source.entry(&columns, &record))
+                .and_then(|entry| plan.add(entry, &self.contractor));
+            if let Err(err) = result {
+                // line 1 holds the headers
+                errors.push(format!("line {}: {}", i + 2, err.message()));
+            }
+        }
+        if !errors.is_empty() {
+            output::note(errors.join("\n"));
+            return Err(CliError::CmdError(format!(
+                "{} entries could not be imported, nothing was written",
+                errors.len()
+            )));
+        }
+        if self.dry_run && !plan.new_hours.is_empty() {
+            output::note(HourLog::format_list(plan.new_hours.clone()));
+        }
+        output::note(plan.format_preview());
+        if self.dry_run {
+            output::note("Dry run, nothing was written");
+            return Ok(());
+        }
+        plan.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+
+    fn parse(source: Source, csv: &str) -> Entry {
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let columns = Columns::new(reader.headers().unwrap());
+        let record = reader.records().next().unwrap().unwrap();
+        source.entry(&columns, &record).unwrap()
+    }
+
+    #[test]
+    fn reads_tracker_exports() {
+        let toggl = parse(
+            Source::Toggl,
+            "\u{feff}User,Email,Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Duration,Tags,Amount (EUR)\n\
+             Ann,ann@x.test,Acme Corp,Website,,Fix login,Yes,2024-03-04,09:00:00,2024-03-04,10:30:29,01:30:29,,",
+        );
+        assert_eq!(toggl.client, "Acme Corp");
+        assert_eq!(toggl.minutes, 90);
+        assert_eq!(toggl.start, Some(NaiveTime::from_hms(9, 0, 0)));
+        assert_eq!(toggl.rate, None);
+
+        let clockify = parse(
+            Source::Clockify,
+            "Project,Client,Description,Task,User,Group,Email,Tags,Billable,Start Date,Start Time,End Date,End Time,Duration (h),Duration (decimal),Billable Rate (USD),Billable Amount (USD)\n\
+             Website,Acme Corp,Fix login,,Ann,,ann@x.test,,Yes,03/04/2024,01:15:00 PM,03/04/2024,02:00:00 PM,00:45:00,0.75,80.00,60.00",
+        );
+        assert_eq!(clockify.date, NaiveDate::from_ymd(2024, 3, 4));
+        assert_eq!(clockify.start, Some(NaiveTime::from_hms(13, 15, 0)));
+        assert_eq!(clockify.minutes, 45);
+        assert_eq!(clockify.rate.unwrap().to_string(), "80.00 USD");
+
+        let harvest = parse(
+            Source::Harvest,
+            "Date,Client,Project,Project Code,Task,Notes,Hours,Hours Rounded,Billable?,Invoiced?,Approved?,First Name,Last Name,Roles,Employee?,Billable Rate,Billable Amount,Cost Rate,Cost Amount,Currency,External Reference URL\n\
+             2024-03-04,Acme Corp,Website,,Development,,1.5,1.5,Yes,No,No,Ann,Lee,,Yes,95.0,142.5,0,0,Euro - EUR,",
+        );
+        assert_eq!(harvest.description, None);
+        assert_eq!(harvest.minutes, 90);
+        assert_eq!(harvest.rate.unwrap().to_string(), "95.00 EUR");
+
+        let yen = parse(
+            Source::Harvest,
+            "Date,Client,Project,Notes,Hours,Billable Rate,Currency
+             2024-03-04,Kaisha,Website,,2,12000.0,Japanese Yen - JPY",
+        );
+        assert_eq!(yen.rate.unwrap().to_string(), "12000 JPY");
+        let dinar = parse(
+            Source::Harvest,
+            "Date,Client,Project,Notes,Hours,Billable Rate,Currency
+             2024-03-04,Sharika,Website,,2,25.5,Kuwaiti Dinar - KWD",
+        );
+        assert_eq!(dinar.rate.unwrap().to_string(), "25.500 KWD");
+    }
+
+    #[test]
+    fn skips_only_existing_bookings() {
+        with_data_dir(&sample(), || {
+            let entry = |description: &str, minutes| Entry {
+                client: "Acme".into(),
+                project: "web".into(),
+                description: Some(description.into()),
+                date: NaiveDate::from_ymd(2024, 3, 4),
+                start: None,
+                minutes,
+                rate: None,
+            };
+            let mut plan = Plan::new().unwrap();
+            plan.add(entry("Landing page", 60), &None).unwrap();
+            plan.add(entry("Standup", 15), &None).unwrap();
+            plan.add(entry("Standup", 15), &None).unwrap();
+            assert_eq!(plan.duplicates, 1);
+            assert_eq!(plan.new_hours.len(), 2);
+            assert_ne!(plan.new_hours[0].id, plan.new_hours[1].id);
+            plan.commit().unwrap();
+            assert_eq!(HourLog::mapping().unwrap().len(), 6);
+        });
+    }
+}
@@ -0,0 +1,875 @@
+use crate::alias::Alias;
+use crate::contractors::Contractor;
+use crate::earnings::Period;
+use crate::errors::CliError;
+use crate::generics::{view_filtered_set, view_subject, Crud, Filter, Result, View};
+use crate::hours::HourLog;
+use crate::money::{Money, Totals};
+use crate::output::{self, Columns};
+use crate::pdf::{Font, Pdf};
+use crate::templates::{html_to_pdf, render_html, InvoiceData};
+use crate::utils::{parse_date, parse_month, partition_directive, unknown_key};
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::{env, fmt, fs};
+use structopt::StructOpt;
+use toml::{from_str as from_toml, to_string as to_toml};
+
+/// Default invoice numbering; override with the `BOOKIT_INVOICE_FORMAT` environment variable.
+/// Supports `{year}`, `{month}` and `{seq}` (or `{seq:<width>}` for zero padding). When the
+/// format contains `{year}` the sequence restarts every year.
+const DEFAULT_NUMBER_FORMAT: &str = "INV-{year}-{seq:4}";
+
+enum InvoiceError {
+    InvalidFilterField(String),
+    InvalidSort(String),
+    NothingToBill(String),
+    Transition(Status, Status),
+    NotDraft(String),
+    UnknownHours(String),
+    AlreadyBilled(String, String),
+}
+
+impl From<InvoiceError> for CliError {
+    fn from(err: InvoiceError) -> Self {
+        match err {
+            InvoiceError::InvalidFilterField(f) => {
+                unknown_key("cannot filter on", &f, &FILTER_FIELDS)
+            }
+            InvoiceError::InvalidSort(s) => unknown_key("cannot sort on", &s, &SORT_KEYS),
+            InvoiceError::NothingToBill(contractor) => Self::CmdError(format!(
+                "no unbilled hours for {} in this period",
+                contractor.yellow().bold()
+            )),
+            InvoiceError::Transition(from, to) => Self::CmdError(format!(
+                "invoice cannot go from {} to {}",
+                from.to_string().yellow().bold(),
+                to.to_string().yellow().bold()
+            )),
+            InvoiceError::NotDraft(number) => Self::CmdError(format!(
+                "invoice {} is not a draft (void it instead)",
+                number.yellow().bold()
+            )),
+            InvoiceError::UnknownHours(id) => {
+                Self::CmdError(format!("hour log {} does not exist", id.yellow().bold()))
+            }
+            InvoiceError::AlreadyBilled(id, number) => Self::CmdError(format!(
+                "hour log {} is already billed on invoice {}",
+                id.yellow().bold(),
+                number.yellow().bold()
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Detail {
+    Alias,
+    Ticket,
+    Day,
+    Booking,
+}
+
+impl FromStr for Detail {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "alias" => Ok(Self::Alias),
+            "ticket" => Ok(Self::Ticket),
+            "day" => Ok(Self::Day),
+            "booking" => Ok(Self::Booking),
+            _ => Err(CliError::Parse {
+                input: input.into(),
+                description: "detail should be one of ( alias | ticket | day | booking )".into(),
+            }),
+        }
+    }
+}
+
+pub const STATUSES: [&str; 4] = ["draft", "sent", "paid", "void"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Draft,
+    Sent,
+    Paid,
+    Void,
+}
+
+impl Status {
+    fn can_become(self, next: Status) -> bool {
+        matches!(
+            (self, next),
+            (Self::Draft, Self::Sent)
+                | (Self::Sent, Self::Paid)
+                | (Self::Draft, Self::Void)
+                | (Self::Sent, Self::Void)
+        )
+    }
+}
+
+impl FromStr for Status {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "draft" => Ok(Self::Draft),
+            "sent" => Ok(Self::Sent),
+            "paid" => Ok(Self::Paid),
+            "void" => Ok(Self::Void),
+            _ => Err(CliError::Parse {
+                input: input.into(),
+                description: "status should be one of ( draft | sent | paid | void )".into(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Draft => "draft",
+            Self::Sent => "sent",
+            Self::Paid => "paid",
+            Self::Void => "void",
+        };
+        f.pad(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Invoice {
+    pub number: String,
+    pub sequence: u32,
+    pub contractor: String,
+    pub period_start: NaiveDate,
+    pub period_end: NaiveDate,
+    pub issue_date: NaiveDate,
+    pub due_date: NaiveDate,
+    pub status: Status,
+    pub detail: Detail,
+    /// Invoice total at the time of creation, one amount per currency
+    pub total: Vec<Money>,
+    /// Ids of the hour logs billed on this invoice
+    pub hours: Vec<String>,
+    /// Lines at the time of creation, so the invoice renders the same after rates change
+    pub lines: Vec<InvoiceLine>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceLine {
+    pub description: String,
+    pub minutes: u32,
+    pub rate: Money,
+    pub amount: Money,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Cmd {
+    /// Create a draft invoice for a contractor and render it to PDF
+    #[structopt(name = "create")]
+    Create(CreateArgs),
+    /// Render an existing invoice to PDF
+    #[structopt(name = "render")]
+    Render {
+        invoice: String,
+        /// Output file (defaults to <number>.pdf)
+        #[structopt(short = "o", long = "out", parse(from_os_str))]
+        out: Option<PathBuf>,
+        /// Write the HTML rendered from the contractor template instead of a PDF
+        #[structopt(long = "html")]
+        html: bool,
+    },
+    /// Change the status of an invoice ( sent | paid | void )
+    #[structopt(name = "status")]
+    Status { invoice: String, status: Status },
+    /// View a collection of invoices
+    #[structopt(name = "show")]
+    Show {
+        #[structopt(short = "f")]
+        filters: Vec<F>,
+        #[structopt(short = "s", default_value = "no_sort")]
+        sort: S,
+    },
+    /// View a detailed invoice
+    #[structopt(name = "detail")]
+    Detail { invoice: String },
+    /// Delete a draft invoice
+    #[structopt(name = "delete")]
+    Delete { invoice: String },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CreateArgs {
+    /// Contractor to invoice
+    #[structopt(short = "c", long = "contractor")]
+    contractor: String,
+    /// Month to invoice (e.g. "YYYY-MM")
+    #[structopt(short = "p", long = "period", parse(try_from_str = parse_month))]
+    period: NaiveDate,
+    /// One invoice line per ( alias | ticket | day | booking )
+    #[structopt(short = "l", long = "detail", default_value = "alias")]
+    detail: Detail,
+    /// Issue date in isoformat or weekday (e.g. "YYYY-MM-DD" | <weekday>)
+    #[structopt(short = "i", long = "issued", default_value = "today", parse(try_from_str = parse_date))]
+    issued: NaiveDate,
+    /// Output file (defaults to <number>.pdf)
+    #[structopt(short = "o", long = "out", parse(from_os_str))]
+    out: Option<PathBuf>,
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        match self {
+            Self::Create(args) => args.exec()?,
+            Self::Render { invoice, out, html } => {
+                Invoice::retrieve(invoice)?.write_pdf(out.clone(), *html)?
+            }
+            Self::Status { invoice, status } => {
+                let mut invoice = Invoice::retrieve(invoice)?;
+                invoice.set_status(*status)?;
+                invoice.overwrite()?
+            }
+            Self::Show { filters, sort } => {
+                view_filtered_set::<Invoice, F, S>(filters.to_vec(), sort.clone())?
+            }
+            Self::Detail { invoice } => view_subject::<Invoice>(Some(invoice.clone()))?,
+            Self::Delete { invoice } => {
+                let invoice = Invoice::retrieve(invoice)?;
+                if invoice.status != Status::Draft {
+                    return Err(InvoiceError::NotDraft(invoice.number).into());
+                }
+                invoice.delete()?
+            }
+        };
+        Ok(())
+    }
+}
+
+impl CreateArgs {
+    fn exec(&self) -> Result<()> {
+        let contractor = Contractor::retrieve(&self.contractor)?;
+        let (start, end) = Period::Month.bounds(self.period);
+        let (aliases, logs) = contractor_hours(&contractor, start, end)?;
+        let billed = Invoice::billed_hours()?;
+        let logs = logs
+            .into_iter()
+            .filter(|log| !billed.contains(&log.id))
+            .collect::<Vec<HourLog>>();
+        if logs.is_empty() {
+            return Err(InvoiceError::NothingToBill(contractor.slug.clone()).into());
+        }
+        let lines = invoice_lines(&logs, &aliases, self.detail);
+        let sequence = Invoice::next_sequence(self.issued)?;
+        let invoice = Invoice {
+            number: format_number(&number_format()?, self.issued, sequence),
+            sequence,
+            contractor: contractor.slug.clone(),
+            period_start: start,
+            period_end: end,
+            issue_date: self.issued,
+            due_date: self.issued + Duration::days(i64::from(contractor.payment_terms)),
+            status: Status::Draft,
+            detail: self.detail,
+            total: total(&lines).amounts(),
+            hours: logs.iter().map(|log| log.identifier()).collect(),
+            lines,
+        };
+        invoice.validate()?;
+        invoice.write_pdf(self.out.clone(), false)?;
+        invoice.add()
+    }
+}
+
+impl Invoice {
+    /// Ids of all hour logs on invoices that have not been voided.
+    pub fn billed_hours() -> Result<HashSet<String>> {
+        Ok(Self::mapping()?
+            .values()
+            .filter(|invoice| invoice.status != Status::Void)
+            .flat_map(|invoice| invoice.hours.clone())
+            .collect())
+    }
+
+    fn next_sequence(issued: NaiveDate) -> Result<u32> {
+        let yearly = number_format()?.contains("{year}");
+        let last = Self::mapping()?
+            .values()
+            .filter(|invoice| !yearly || invoice.issue_date.year() == issued.year())
+            .map(|invoice| invoice.sequence)
+            .max()
+            .unwrap_or(0);
+        Ok(last + 1)
+    }
+
+    /// Moves the invoice to `status` when its lifecycle allows it (see `Status::can_become`).
+    pub fn set_status(&mut self, status: Status) -> Result<()> {
+        if !self.status.can_become(status) {
+            return Err(InvoiceError::Transition(self.status, status).into());
+        }
+        self.status = status;
+        Ok(())
+    }
+
+    /// Hour logs billed on this invoice, ordered by date.
+    pub fn hour_logs(&self) -> Result<Vec<HourLog>> {
+        let ids = self.hours.iter().collect::<HashSet<&String>>();
+        let mut logs = HourLog::get_default_items(None, None)?
+            .into_iter()
+            .filter(|log| ids.contains(&log.id))
+            .collect::<Vec<HourLog>>();
+        logs.sort_by_key(|log| (log.date, log.timestamp));
+        Ok(logs)
+    }
+
+    /// Renders the invoice with the contractor template when one is set and with the
+    /// built-in layout otherwise. With `html` the template output is written as is.
+    fn write_pdf(&self, out: Option<PathBuf>, html: bool) -> Result<()> {
+        let contractor = Contractor::retrieve(&self.contractor)?;
+        let aliases = Alias::mapping()?;
+        let logs = self.hour_logs()?;
+        let extension = if html { "html" } else { "pdf" };
+        let out = out.unwrap_or_else(|| PathBuf::from(format!("{}.{}", self.number, extension)));
+        match &contractor.template {
+            Some(template) => {
+                let data = InvoiceData::new(self, &contractor, &aliases, &logs);
+                let rendered = render_html(template, &data)?;
+                if html {
+                    fs::write(&out, rendered).map_err(CliError::Write)?
+                } else {
+                    html_to_pdf(&rendered, &out)?
+                }
+            }
+            None if html => {
+                return Err(CliError::CmdError(format!(
+                    "contractor {} has no invoice template",
+                    contractor.slug.yellow().bold()
+                )))
+            }
+            None => fs::write(&out, render_pdf(self, &contractor, &self.lines))
+                .map_err(CliError::Write)?,
+        };
+        output::note(format!(
+            "Invoice {} written to {}",
+            self.number,
+            out.display()
+        ));
+        Ok(())
+    }
+
+    fn format_total(&self) -> String {
+        let mut total = Totals::default();
+        for amount in &self.total {
+            total.add(amount);
+        }
+        total.to_string()
+    }
+}
+
+/// The format from `BOOKIT_INVOICE_FORMAT`, which needs a `{seq}` to keep numbers unique.
+fn number_format() -> Result<String> {
+    let format = env::var("BOOKIT_INVOICE_FORMAT").unwrap_or_else(|_| DEFAULT_NUMBER_FORMAT.into());
+    match format.contains("{seq") {
+        true => Ok(format),
+        false => Err(CliError::CmdError(format!(
+            "BOOKIT_INVOICE_FORMAT {} has no {{seq}} placeholder",
+            format.yellow().bold()
+        ))),
+    }
+}
+
+fn format_number(format: &str, issued: NaiveDate, sequence: u32) -> String {
+    let mut number = format
+        .replace("{year}", &issued.year().to_string())
+        .replace("{month}", &format!("{:02}", issued.month()));
+    while let Some(start) = number.find("{seq") {
+        let end = match number[start..].find('}') {
+            Some(offset) => start + offset,
+            None => break,
+        };
+        let width = number[start + 4..end]
+            .trim_start_matches(':')
+            .parse::<usize>()
+            .unwrap_or(0);
+        let seq = format!("{:0width$}", sequence, width = width);
+        number.replace_range(start..=end, &seq);
+    }
+    number
+}
+
+impl Crud for Invoice {
+    const FILE: &'static str = "invoices_test.toml";
+
+    fn identifier(&self) -> String {
+        self.number.clone()
+    }
+
+    fn deserialize(tomlstr: String) -> Result<HashMap<String, Invoice>> {
+        Ok(from_toml(&tomlstr)?)
+    }
+
+    fn serialize(map: HashMap<String, Invoice>) -> Result<String> {
+        Ok(to_toml(&map)?)
+    }
+
+    /// The contractor and hour logs must exist, and unless this invoice is void its hours
+    /// may not be on another invoice that is not void.
+    fn validate(&self) -> Result<()> {
+        Contractor::write_ok(&self.contractor, true)?;
+        let logs = HourLog::mapping()?;
+        if let Some(id) = self.hours.iter().find(|id| !logs.contains_key(*id)) {
+            return Err(InvoiceError::UnknownHours(id.clone()).into());
+        }
+        if self.status == Status::Void {
+            return Ok(());
+        }
+        for other in Self::mapping()?.values() {
+            if other.number == self.number || other.status == Status::Void {
+                continue;
+            }
+            if let Some(id) = self.hours.iter().find(|id| other.hours.contains(id)) {
+                return Err(InvoiceError::AlreadyBilled(id.clone(), other.number.clone()).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn interactive_update(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl Columns for Invoice {
+    const COLUMNS: &'static [&'static str] = &[
+        "number",
+        "sequence",
+        "contractor",
+        "period_start",
+        "period_end",
+        "issue_date",
+        "due_date",
+        "status",
+        "detail",
+        "total",
+        "hours",
+        "lines",
+    ];
+}
+
+impl View for Invoice {
+    fn format_list_item(&self) -> String {
+        let status = match self.status {
+            Status::Draft => self.status.to_string().yellow(),
+            Status::Sent => self.status.to_string().blue(),
+            Status::Paid => self.status.to_string().green(),
+            Status::Void => self.status.to_string().dimmed(),
+        };
+        format!(
+            "{:14} {:7} {} {:5} {}",
+            self.number.red().bold(),
+            self.contractor.cyan(),
+            self.issue_date,
+            status,
+            self.format_total().green().bold()
+        )
+    }
+
+    fn format_detail(&self) -> String {
+        let overdue = self.status == Status::Sent && Local::today().naive_local() > self.due_date;
+        let fields = vec![
+            (
+                "Period",
+                format!("{} - {}", self.period_start, self.period_end),
+            ),
+            (
+                "Due",
+                match overdue {
+                    true => format!("{} {}", self.due_date, "(overdue)".red().bold()),
+                    false => self.due_date.to_string(),
+                },
+            ),
+            ("Hour logs", self.hours.join(" ")),
+        ];
+        let mut lines = vec![self.format_list_item()];
+        for (label, value) in fields {
+            lines.push(format!("  {:10} {}", format!("{}:", label).bold(), value));
+        }
+        lines.join("\n")
+    }
+}
+
+pub const FILTER_FIELDS: [&str; 2] = ["contractor", "status"];
+pub const SORT_KEYS: [&str; 3] = ["no_sort", "issued", "number"];
+
+#[derive(Debug, Clone)]
+pub enum F {
+    NoFilter,
+    Contractor(String),
+    Status(Status),
+}
+
+impl FromStr for F {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match partition_directive(input)? {
+            ("contractor", val) => Ok(Self::Contractor(val.to_string())),
+            ("status", val) => Ok(Self::Status(Status::from_str(val)?)),
+            (field, _) => Err(InvoiceError::InvalidFilterField(field.to_owned()).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum S {
+    NoSort,
+    ByIssueDate,
+    ByNumber,
+}
+
+impl FromStr for S {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "no_sort" => Ok(Self::NoSort),
+            "issued" => Ok(Self::ByIssueDate),
+            "number" => Ok(Self::ByNumber),
+            s => Err(InvoiceError::InvalidSort(s.to_owned()).into()),
+        }
+    }
+}
+
+impl Filter<F, S> for Invoice {
+    const DEFAULT_SORT: S = S::NoSort;
+    const DEFAULT_FILTER: F = F::NoFilter;
+
+    fn get_base_items() -> Result<Vec<Self>> {
+        let mapping = Self::mapping()?;
+        Ok(mapping.values().cloned().collect::<Vec<Self>>())
+    }
+
+    fn filter(items: Vec<Self>, method: F) -> Result<Vec<Self>> {
+        Ok(match method {
+            F::NoFilter => items,
+            F::Contractor(contractor) => items
+                .into_iter()
+                .filter(|item| item.contractor == contractor)
+                .collect(),
+            F::Status(status) => items
+                .into_iter()
+                .filter(|item| item.status == status)
+                .collect(),
+        })
+    }
+
+    fn sort(items: Vec<Self>, method: S) -> Vec<Self> {
+        let mut items = items;
+        match method {
+            S::NoSort => (),
+            S::ByIssueDate => items.sort_by_key(|item| (item.issue_date, item.sequence)),
+            S::ByNumber => items.sort_by(|a, b| a.number.cmp(&b.number)),
+        };
+        items
+    }
+}
+
+/// Aliases of a contractor and all hours booked on them between `start` and `end` (inclusive).
+pub fn contractor_hours(
+    contractor: &Contractor,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<(HashMap<String, Alias>, Vec<HourLog>)> {
+    let aliases = Alias::get_default_items(None, None)?
+        .into_iter()
+        .filter(|alias| alias.contractor == contractor.slug)
+        .map(|alias| (alias.identifier(), alias))
+        .collect::<HashMap<String, Alias>>();
+    let mut logs = HourLog::get_default_items(None, None)?
+        .into_iter()
+        .filter(|log| aliases.contains_key(&log.alias) && log.date >= start && log.date <= end)
+        .collect::<Vec<HourLog>>();
+    if logs.is_empty() {
+        return Err(CliError::FilterNoResults);
+    }
+    logs.sort_by_key(|log| (log.date, log.timestamp));
+    Ok((aliases, logs))
+}
+
+/// Groups hour logs into invoice lines. Hours booked at different rates never share a line.
+pub fn invoice_lines(
+    logs: &[HourLog],
+    aliases: &HashMap<String, Alias>,
+    detail: Detail,
+) -> Vec<InvoiceLine> {
+    let mut lines: BTreeMap<(String, String, String), InvoiceLine> = BTreeMap::new();
+    for log in logs {
+        let alias = match aliases.get(&log.alias) {
+            Some(alias) => alias,
+            None => continue,
+        };
+        let rate = alias.rate_on(log.date).clone();
+        let (key, description) = match detail {
+            Detail::Alias => (
+                (alias.slug.clone(), String::new(), rate.to_string()),
+                alias.short_description.clone(),
+            ),
+            Detail::Ticket => (
+                (
+                    alias.slug.clone(),
+                    log.ticket.clone().unwrap_or_default(),
+                    rate.to_string(),
+                ),
+                match &log.ticket {
+                    Some(t) => format!("{} [{}]", alias.short_description, t),
+                    None => alias.short_description.clone(),
+                },
+            ),
+            Detail::Day => (
+                (log.date.to_string(), alias.slug.clone(), rate.to_string()),
+                format!("{} {}", log.date, alias.short_description),
+            ),
+            Detail::Booking => (
+                (
+                    log.date.to_string(),
+                    log.timestamp.to_string(),
+                    log.id.clone(),
+                ),
+                format!(
+                    "{} {}{}",
+                    log.date,
+                    match &log.ticket {
+                        Some(t) => format!("[{}] ", t),
+                        None => String::new(),
+                    },
+                    log.message
+                        .clone()
+                        .unwrap_or_else(|| alias.short_description.clone())
+                ),
+            ),
+        };
+        let line = lines.entry(key).or_insert_with(|| InvoiceLine {
+            description,
+            minutes: 0,
+            amount: Money::zero(rate.currency.clone()),
+            rate,
+        });
+        line.minutes += log.minutes;
+    }
+    lines
+        .into_values()
+        .map(|mut line| {
+            line.amount = line.rate.for_minutes(line.minutes);
+            line
+        })
+        .collect()
+}
+
+pub fn total(lines: &[InvoiceLine]) -> Totals {
+    let mut total = Totals::default();
+    for line in lines {
+        total.add(&line.amount);
+    }
+    total
+}
+
+pub fn format_hours(minutes: u32) -> String {
+    format!("{:.2}", f64::from(minutes) / 60.0)
+}
+
+fn render_pdf(invoice: &Invoice, contractor: &Contractor, lines: &[InvoiceLine]) -> Vec<u8> {
+    let mut pdf = Pdf::new();
+    pdf.text(Font::Bold, 20.0, &format!("Invoice {}", invoice.number));
+    pdf.space(10.0);
+    pdf.text(Font::Bold, 11.0, &contractor.name);
+    for field in [&contractor.address, &contractor.email, &contractor.vat_id]
+        .iter()
+        .filter_map(|f| f.as_ref())
+    {
+        pdf.text(Font::Regular, 10.0, field);
+    }
+    pdf.space(10.0);
+    for (label, value) in [
+        ("Issue date", invoice.issue_date.to_string()),
+        ("Due date", invoice.due_date.to_string()),
+        (
+            "Period",
+            format!("{} - {}", invoice.period_start, invoice.period_end),
+        ),
+    ] {
+        pdf.text(Font::Regular, 10.0, &format!("{}: {}", label, value));
+    }
+    pdf.space(10.0);
+    let row = |description: &str, hours: &str, rate: &str, amount: &str| {
+        let description = description.chars().take(40).collect::<String>();
+        format!(
+            "{:40} {:>8} {:>14} {:>14}",
+            description, hours, rate, amount
+        )
+    };
+    pdf.text(
+        Font::Mono,
+        9.0,
+        &row("Description", "Hours", "Rate", "Amount"),
+    );
+    pdf.text(Font::Mono, 9.0, &"-".repeat(79));
+    for line in lines {
+        pdf.text(
+            Font::Mono,
+            9.0,
+            &row(
+                &line.description,
+                &format_hours(line.minutes),
+                &line.rate.to_string(),
+                &line.amount.to_string(),
+            ),
+        );
+    }
+    pdf.text(Font::Mono, 9.0, &"-".repeat(79));
+    let minutes = lines.iter().map(|line| line.minutes).sum();
+    for (i, amount) in total(lines).amounts().iter().enumerate() {
+        let (label, hours) = match i {
+            0 => ("Total", format_hours(minutes)),
+            _ => ("", String::new()),
+        };
+        pdf.text(
+            Font::Mono,
+            9.0,
+            &row(label, &hours, "", &amount.to_string()),
+        );
+    }
+    pdf.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+    use crate::money::Currency;
+    use chrono::NaiveDateTime;
+
+    fn log(id: &str, date: NaiveDate, minutes: u32, ticket: Option<&str>) -> HourLog {
+        HourLog {
+            alias: "web".into(),
+            minutes,
+            date,
+            message: None,
+            ticket: ticket.map(String::from),
+            branch: None,
+            id: id.into(),
+            timestamp: NaiveDateTime::from_timestamp(0, 0),
+        }
+    }
+
+    #[test]
+    fn lines_split_on_detail_and_rate() {
+        let eur = Currency::default();
+        let mut alias = Alias {
+            slug: "web".into(),
+            contractor: "acme".into(),
+            short_description: "Website".into(),
+            hourly_rate: Money::new(6000, eur.clone()),
+            rate_changes: vec![],
+        };
+        alias.schedule_rate(NaiveDate::from_ymd(2020, 7, 15), Money::new(12000, eur));
+        let aliases = vec![("web".to_string(), alias)].into_iter().collect();
+        let logs = vec![
+            log("a", NaiveDate::from_ymd(2020, 7, 1), 60, Some("T-1")),
+            log("b", NaiveDate::from_ymd(2020, 7, 2), 30, Some("T-2")),
+            log("c", NaiveDate::from_ymd(2020, 7, 20), 30, Some("T-1")),
+        ];
+        let by_alias = invoice_lines(&logs, &aliases, Detail::Alias);
+        assert_eq!(by_alias.len(), 2);
+        assert_eq!(total(&by_alias).to_string(), "150.00 EUR");
+        assert_eq!(invoice_lines(&logs, &aliases, Detail::Ticket).len(), 3);
+        assert_eq!(invoice_lines(&logs, &aliases, Detail::Booking).len(), 3);
+    }
+
+    #[test]
+    fn keeps_the_lines_it_was_created_with() {
+        let eur = Currency::default();
+        let invoice = Invoice {
+            number: "INV-2020-0001".into(),
+            sequence: 1,
+            contractor: "acme".into(),
+            period_start: NaiveDate::from_ymd(2020, 7, 1),
+            period_end: NaiveDate::from_ymd(2020, 7, 31),
+            issue_date: NaiveDate::from_ymd(2020, 7, 31),
+            due_date: NaiveDate::from_ymd(2020, 8, 30),
+            status: Status::Draft,
+            detail: Detail::Alias,
+            total: vec![Money::new(6000, eur.clone())],
+            hours: vec!["a".into()],
+            lines: vec![InvoiceLine {
+                description: "Website".into(),
+                minutes: 60,
+                rate: Money::new(6000, eur.clone()),
+                amount: Money::new(6000, eur),
+            }],
+        };
+        let stored =
+            <Invoice as Crud>::serialize(HashMap::from([(invoice.number.clone(), invoice)]))
+                .unwrap();
+        let invoice =
+            <Invoice as Crud>::deserialize(stored.clone()).unwrap()["INV-2020-0001"].clone();
+        assert_eq!(total(&invoice.lines).to_string(), "60.00 EUR");
+        // an invoice without lines cannot be rendered and is not silently recalculated
+        let without_lines = stored
+            .lines()
+            .take_while(|line| !line.contains("lines"))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        assert!(<Invoice as Crud>::deserialize(without_lines).is_err());
+    }
+
+    #[test]
+    fn invoice_number_format() {
+        let issued = NaiveDate::from_ymd(2020, 7, 31);
+        assert_eq!(
+            format_number(DEFAULT_NUMBER_FORMAT, issued, 12),
+            "INV-2020-0012"
+        );
+        assert_eq!(format_number("{year}{month}-{seq}", issued, 3), "202007-3");
+        with_data_dir(&[], || {
+            env::set_var("BOOKIT_INVOICE_FORMAT", "INV-{year}");
+            let without_sequence = number_format();
+            env::remove_var("BOOKIT_INVOICE_FORMAT");
+            assert!(without_sequence.is_err());
+        });
+    }
+
+    #[test]
+    fn status_lifecycle() {
+        assert!(Status::Draft.can_become(Status::Sent));
+        assert!(Status::Sent.can_become(Status::Void));
+        assert!(!Status::Paid.can_become(Status::Void));
+        assert!(!Status::Void.can_become(Status::Draft));
+    }
+
+    #[test]
+    fn hours_exist_and_are_billed_once() {
+        with_data_dir(&sample(), || {
+            let mut invoice = Invoice::retrieve("INV-2024-0002").unwrap();
+            assert!(invoice.validate().is_ok());
+            invoice.hours = vec!["nope".into()];
+            assert!(invoice.validate().is_err());
+            // w1 is on INV-2024-0001, which is sent
+            invoice.hours = vec!["w2".into(), "w1".into()];
+            assert!(invoice.validate().is_err());
+            invoice.status = Status::Void;
+            assert!(invoice.validate().is_ok());
+            let mut first = Invoice::retrieve("INV-2024-0001").unwrap();
+            first.status = Status::Void;
+            first.overwrite().unwrap();
+            invoice.status = Status::Draft;
+            assert!(invoice.validate().is_ok());
+        });
+    }
+}
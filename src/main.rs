@@ -1,15 +1,43 @@
 mod alias;
+mod completions;
 mod contractors;
+mod earnings;
+mod edit;
 mod errors;
+mod export;
 mod generics;
 mod hours;
+mod import;
+mod invoice;
+mod money;
+mod output;
+mod pdf;
+mod serve;
+mod shell;
+mod site;
+mod templates;
+mod tui;
 mod utils;
+use std::process;
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 #[macro_use]
 extern crate pipeline;
 
 #[derive(StructOpt, Debug)]
-enum Opt {
+struct Opt {
+    /// Output format of view commands ( table | json | jsonl | csv | tsv )
+    #[structopt(long = "output", global = true, default_value = "table")]
+    output: output::Output,
+    /// Print only requested data, no status messages or errors (see the exit code)
+    #[structopt(short = "q", long = "quiet", global = true)]
+    quiet: bool,
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
     /// Manage aliases
     #[structopt(name = "alias")]
     Alias(alias::Cmd),
@@ -19,16 +47,116 @@ enum Opt {
     /// Manage hours
     #[structopt(name = "hours")]
     Hours(hours::Cmd),
+    /// Report billable earnings per contractor
+    #[structopt(name = "earnings")]
+    Earnings(earnings::Cmd),
+    /// Edit a record in $EDITOR
+    #[structopt(name = "edit")]
+    Edit(edit::Cmd),
+    /// Export data for use in other tools
+    #[structopt(name = "export")]
+    Export(export::Cmd),
+    /// Import time entries from other time trackers
+    #[structopt(name = "import")]
+    Import(import::Cmd),
+    /// Generate invoices
+    #[structopt(name = "invoice")]
+    Invoice(invoice::Cmd),
+    /// Generate static report sites for clients
+    #[structopt(name = "site")]
+    Site(site::Cmd),
+    /// Serve a local JSON API over contractors, aliases and hours
+    #[structopt(name = "serve")]
+    Serve(serve::Cmd),
+    /// Generate shell completions ( bash | zsh | fish )
+    #[structopt(name = "completions")]
+    Completions(completions::Cmd),
+    /// Run commands in an interactive shell that keeps the data in memory
+    #[structopt(name = "shell")]
+    Shell(shell::Cmd),
+    /// Open a full-screen dashboard of this week's bookings with a timer
+    #[structopt(name = "tui")]
+    Tui(tui::Cmd),
 }
 
-fn main() -> () {
-    let r = match Opt::from_args() {
-        Opt::Alias(cmd) => cmd.exec(),
-        Opt::Contractors(cmd) => cmd.exec(),
-        Opt::Hours(cmd) => cmd.exec(),
+fn main() {
+    // the hidden completion helper sits outside of the grammar, its words are partial commands
+    let args = std::env::args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect::<Vec<String>>();
+    if args.first().map(String::as_str) == Some("__complete") {
+        let _ = completions::complete(&Opt::clap(), &args[1..]);
+        return;
+    }
+    let opt = match Opt::from_iter_safe(std::env::args_os()) {
+        Ok(opt) => opt,
+        Err(err) if err.use_stderr() => {
+            if !quiet_requested(&args) {
+                eprintln!("{}", err.message);
+            }
+            // argument values that fail to parse exit like parse errors, other misuse as usage errors
+            let code = match err.kind {
+                ErrorKind::ValueValidation | ErrorKind::InvalidValue => 3,
+                _ => 2,
+            };
+            process::exit(code)
+        }
+        Err(err) => err.exit(),
     };
-    match r {
-        Ok(_) => (),
-        Err(e) => println!("{}", e),
+    if let Err(e) = run(opt) {
+        if !output::quiet() {
+            eprintln!("{}", e);
+        }
+        process::exit(e.exit_code());
+    }
+}
+
+fn run(opt: Opt) -> generics::Result<()> {
+    output::set(opt.output);
+    output::set_quiet(opt.quiet);
+    match opt.cmd {
+        Command::Alias(cmd) => cmd.exec(),
+        Command::Contractors(cmd) => cmd.exec(),
+        Command::Hours(cmd) => cmd.exec(),
+        Command::Earnings(cmd) => cmd.exec(),
+        Command::Edit(cmd) => cmd.exec(),
+        Command::Export(cmd) => cmd.exec(),
+        Command::Import(cmd) => cmd.exec(),
+        Command::Invoice(cmd) => cmd.exec(),
+        Command::Site(cmd) => cmd.exec(),
+        Command::Serve(cmd) => cmd.exec(),
+        Command::Completions(cmd) => cmd.exec(Opt::clap()),
+        Command::Shell(cmd) => cmd.exec(Opt::clap(), run_line),
+        Command::Tui(cmd) => cmd.exec(),
+    }
+}
+
+/// Whether `--quiet` is among the arguments, for errors found before they are parsed.
+fn quiet_requested(args: &[String]) -> bool {
+    args.iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "-q" || arg == "--quiet")
+}
+
+/// Runs a line of `bookit shell`; usage errors and help are printed like on the command line.
+fn run_line(words: Vec<String>) -> generics::Result<()> {
+    let args = std::iter::once("bookit".to_string()).chain(words.clone());
+    match Opt::from_iter_safe(args) {
+        Ok(Opt {
+            cmd: Command::Shell(_),
+            ..
+        }) => Err(errors::CliError::CmdError("already in the shell".into())),
+        Ok(opt) => run(opt),
+        Err(err) if err.use_stderr() => {
+            if !quiet_requested(&words) {
+                eprintln!("{}", err.message);
+            }
+            Ok(())
+        }
+        Err(err) => {
+            println!("{}", err.message);
+            Ok(())
+        }
     }
 }
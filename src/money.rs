@@ -0,0 +1,302 @@
+use crate::errors::CliError;
+use crate::generics::Result;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// ISO 4217 currency code (e.g. "EUR").
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency(String);
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self("EUR".into())
+    }
+}
+
+impl Currency {
+    /// Number of decimals in the minor unit of the currency.
+    pub fn exponent(&self) -> u32 {
+        match self.0.as_ref() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF"
+            | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let code = input.trim().to_uppercase();
+        if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+            Ok(Self(code))
+        } else {
+            Err(CliError::Parse {
+                input: input.into(),
+                description: "currency should be a three letter ISO 4217 code (e.g. EUR)".into(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Currency::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// An amount of money stored in minor units (e.g. cents) of its currency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    pub minor: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor: i64, currency: Currency) -> Self {
+        Self { minor, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(0, currency)
+    }
+
+    /// A whole `amount` of the currency (e.g. 90 EUR), as legacy rates were stored.
+    pub fn whole(amount: i64, currency: Currency) -> Self {
+        Self::new(amount * 10i64.pow(currency.exponent()), currency)
+    }
+
+    /// Parses an amount like "90" or "87.50", with an optional currency code
+    /// before or after it. Falls back to `default` when no code is given.
+    pub fn parse(input: &str, default: &Currency) -> Result<Self> {
+        let parts = input.split_whitespace().collect::<Vec<&str>>();
+        let (amount, currency) = match parts.as_slice() {
+            [amount] => (*amount, default.clone()),
+            [a, b] if a.chars().all(|c| c.is_ascii_alphabetic()) => (*b, Currency::from_str(a)?),
+            [a, b] => (*a, Currency::from_str(b)?),
+            _ => return Err(Self::parse_error(input)),
+        };
+        let minor =
+            parse_minor(amount, currency.exponent()).ok_or_else(|| Self::parse_error(input))?;
+        Ok(Self::new(minor, currency))
+    }
+
+    fn parse_error(input: &str) -> CliError {
+        CliError::Parse {
+            input: input.into(),
+            description: "amount should be formatted as <amount> [currency] (e.g. 87.50 EUR)"
+                .into(),
+        }
+    }
+
+    /// Value of `minutes` of work at this hourly amount, rounded to the nearest minor unit.
+    pub fn for_minutes(&self, minutes: u32) -> Self {
+        let product = self.minor * i64::from(minutes);
+        let minor = (product + product.signum() * 30) / 60;
+        Self::new(minor, self.currency.clone())
+    }
+
+    /// Amount without the currency code (e.g. "87.50").
+    pub fn format_amount(&self) -> String {
+        let exponent = self.currency.exponent();
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let abs = self.minor.unsigned_abs();
+        if exponent == 0 {
+            return format!("{}{}", sign, abs);
+        }
+        let scale = 10u64.pow(exponent);
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            abs / scale,
+            abs % scale,
+            width = exponent as usize
+        )
+    }
+}
+
+fn parse_minor(amount: &str, exponent: u32) -> Option<i64> {
+    let (negative, amount) = match amount.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, amount),
+    };
+    let mut split = amount.splitn(2, '.');
+    let whole = split.next()?;
+    let fraction = split.next().unwrap_or("");
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > exponent as usize
+    {
+        return None;
+    }
+    let whole = whole.parse::<i64>().ok()?;
+    let fraction = format!("{:0<width$}", fraction, width = exponent as usize);
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<i64>().ok()?
+    };
+    let minor = whole
+        .checked_mul(10i64.pow(exponent))?
+        .checked_add(fraction)?;
+    Some(if negative { -minor } else { minor })
+}
+
+impl FromStr for Money {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Self::parse(input, &Currency::default())
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = format!("{} {}", self.format_amount(), self.currency);
+        f.pad(&s)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct MoneyVisitor;
+
+impl<'de> Visitor<'de> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an amount like \"87.50 EUR\" or a whole number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Money, E> {
+        Money::from_str(v).map_err(E::custom)
+    }
+
+    // Rates used to be stored as bare integers without a currency; aliases read them in the
+    // currency of their contractor before they get here (see `Alias::deserialize`).
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Money, E> {
+        Ok(Money::whole(v as i64, Currency::default()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<Money, E> {
+        Ok(Money::whole(v, Currency::default()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+/// Sums of money kept apart per currency.
+#[derive(Debug, Default, Clone)]
+pub struct Totals(BTreeMap<Currency, i64>);
+
+impl Totals {
+    pub fn add(&mut self, money: &Money) {
+        *self.0.entry(money.currency.clone()).or_insert(0) += money.minor;
+    }
+
+    pub fn merge(&mut self, other: &Totals) {
+        for money in other.amounts() {
+            self.add(&money);
+        }
+    }
+
+    pub fn amounts(&self) -> Vec<Money> {
+        self.0
+            .iter()
+            .map(|(currency, minor)| Money::new(*minor, currency.clone()))
+            .collect()
+    }
+}
+
+impl Serialize for Totals {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self.0.len() {
+            0 => Money::zero(Currency::default()).to_string(),
+            _ => self
+                .amounts()
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<String>>()
+                .join(" + "),
+        };
+        f.pad(&s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::*;
+
+    #[test]
+    fn parse_with_currency() {
+        let eur = Currency::default();
+        assert_eq!(
+            Money::parse("87.5 usd", &eur).unwrap().to_string(),
+            "87.50 USD"
+        );
+        assert_eq!(
+            Money::parse("JPY 1200", &eur).unwrap().to_string(),
+            "1200 JPY"
+        );
+        assert_eq!(Money::parse("90", &eur).unwrap().minor, 9000);
+        assert!(Money::parse("1.234", &eur).is_err());
+        assert!(Money::parse("12,50", &eur).is_err());
+    }
+
+    #[test]
+    fn legacy_integer_rate() {
+        #[derive(Deserialize)]
+        struct Legacy {
+            rate: Money,
+        }
+        let legacy: Legacy = toml::from_str("rate = 90").unwrap();
+        assert_eq!(legacy.rate, Money::new(9000, Currency::default()));
+    }
+
+    #[test]
+    fn amount_for_minutes_rounds() {
+        let rate = Money::new(10000, Currency::default());
+        assert_eq!(rate.for_minutes(90).minor, 15000);
+        assert_eq!(Money::new(1, Currency::default()).for_minutes(30).minor, 1);
+    }
+
+    proptest! {
+        #[test]
+        fn display_parse_roundtrip(minor in -1_000_000_000i64..1_000_000_000) {
+            let money = Money::new(minor, Currency::default());
+            assert_eq!(Money::from_str(&money.to_string()).unwrap(), money);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Output formats of the view commands. The format is chosen once per invocation with the
+//! global `--output` flag; everything but `table` is meant for scripts and contains the
+//! serialized field names of the records and no ANSI codes.
+use crate::errors::CliError;
+use crate::generics::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+static OUTPUT: RwLock<Output> = RwLock::new(Output::Table);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Table,
+    Json,
+    Jsonl,
+    Csv,
+    Tsv,
+}
+
+impl FromStr for Output {
+    type Err = CliError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            _ => Err(CliError::Parse {
+                input: input.into(),
+                description: "output should be one of ( table | json | jsonl | csv | tsv )".into(),
+            }),
+        }
+    }
+}
+
+/// Sets the output format of the current command; machine formats also turn off colors.
+pub fn set(output: Output) {
+    if let Ok(mut current) = OUTPUT.write() {
+        *current = output;
+    }
+    if output.is_machine() {
+        colored::control::set_override(false);
+    } else {
+        colored::control::unset_override();
+    }
+}
+
+pub fn current() -> Output {
+    OUTPUT.read().map_or(Output::Table, |current| *current)
+}
+
+/// With `--quiet` only the requested data is printed: no status messages and no errors.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a status message (what was written, warnings) to stderr unless `--quiet` is set.
+pub fn note(message: impl Display) {
+    if !quiet() {
+        eprintln!("{}", message);
+    }
+}
+
+/// Writes rendered output to stdout; a closed pipe (e.g. `| head`) is not an error.
+pub fn emit(rendered: &str) -> Result<()> {
+    if rendered.is_empty() {
+        return Ok(());
+    }
+    match writeln!(io::stdout(), "{}", rendered) {
+        Err(io_err) if io_err.kind() != io::ErrorKind::BrokenPipe => Err(CliError::Write(io_err)),
+        _ => Ok(()),
+    }
+}
+
+/// Records that can be rendered as rows. `COLUMNS` are the serialized field names in the
+/// order of the header, which stays the same whichever optional fields a record leaves out.
+pub trait Columns {
+    const COLUMNS: &'static [&'static str];
+}
+
+impl Output {
+    pub fn is_machine(self) -> bool {
+        self != Self::Table
+    }
+
+    /// Renders a list of records; `Table` is handled by `View` and renders nothing here.
+    pub fn render_list<T: Serialize + Columns>(self, items: &[T]) -> Result<String> {
+        match self {
+            Self::Table => Ok(String::new()),
+            Self::Json => Ok(serde_json::to_string_pretty(items)?),
+            Self::Jsonl => Ok(items
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<Vec<String>, _>>()?
+                .join("\n")),
+            Self::Csv => delimited(items, b','),
+            Self::Tsv => delimited(items, b'\t'),
+        }
+    }
+
+    /// Renders a single record, as an object in `Json` and as a one row table otherwise.
+    pub fn render_one<T: Serialize + Columns>(self, item: &T) -> Result<String> {
+        match self {
+            Self::Json => Ok(serde_json::to_string_pretty(item)?),
+            _ => self.render_list(std::slice::from_ref(item)),
+        }
+    }
+}
+
+/// Writes records as rows under a header of `T::COLUMNS`; nested values are written as JSON.
+fn delimited<T: Serialize + Columns>(items: &[T], delimiter: u8) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    writer.write_record(T::COLUMNS)?;
+    for item in items {
+        let fields = match serde_json::to_value(item)? {
+            Value::Object(fields) => fields,
+            _ => Default::default(),
+        };
+        writer.write_record(T::COLUMNS.iter().map(|column| match fields.get(*column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(value) => value.to_string(),
+        }))?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| CliError::Serialization(e.to_string()))?;
+    let text = String::from_utf8_lossy(&bytes);
+    Ok(text.trim_end_matches('\n').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alias::Alias;
+    use crate::contractors::Contractor;
+    use crate::generics::testing::{assert_columns, sample, with_data_dir};
+    use crate::generics::Crud;
+    use crate::hours::HourLog;
+    use crate::invoice::Invoice;
+    use serde_json::json;
+
+    #[derive(Serialize)]
+    struct Record {
+        slug: &'static str,
+        note: Option<&'static str>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        rate_changes: Vec<Value>,
+    }
+
+    impl Columns for Record {
+        const COLUMNS: &'static [&'static str] = &["slug", "note", "rate_changes"];
+    }
+
+    #[test]
+    fn delimited_output_flattens_records() {
+        let items = vec![
+            Record {
+                slug: "web",
+                note: None,
+                rate_changes: vec![json!({"rate": "80.00 EUR"})],
+            },
+            Record {
+                slug: "ops, infra",
+                note: None,
+                rate_changes: vec![],
+            },
+        ];
+        assert_columns(&items);
+        assert_eq!(
+            Output::Csv.render_list(&items).unwrap(),
+            "slug,note,rate_changes\nweb,,\"[{\"\"rate\"\":\"\"80.00 EUR\"\"}]\"\n\"ops, infra\",,"
+        );
+        // the header does not depend on the optional fields of the records
+        assert_eq!(
+            Output::Tsv.render_list(&items[1..]).unwrap(),
+            "slug\tnote\trate_changes\nops, infra\t\t"
+        );
+        assert_eq!(
+            Output::Jsonl.render_list(&items[1..]).unwrap(),
+            "{\"slug\":\"ops, infra\",\"note\":null}"
+        );
+        // without records there is still a header to tell an empty result from a failure
+        assert_eq!(
+            Output::Csv.render_list::<Record>(&[]).unwrap(),
+            "slug,note,rate_changes"
+        );
+    }
+
+    #[test]
+    fn records_declare_their_columns() {
+        with_data_dir(&sample(), || {
+            fn records<T: Crud>() -> Vec<T> {
+                T::mapping().unwrap().into_values().collect()
+            }
+            assert_columns(&records::<Contractor>());
+            assert_columns(&records::<Alias>());
+            // every alias has the same fields, also without scheduled rate changes
+            assert_columns(&[Alias::retrieve("api").unwrap()]);
+            assert_columns(&records::<HourLog>());
+            assert_columns(&records::<Invoice>());
+        });
+    }
+}
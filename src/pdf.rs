@@ -0,0 +1,189 @@
+//! Minimal PDF writer for text documents using the standard Type 1 fonts,
+//! which every PDF reader ships with, so no fonts need to be embedded.
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Font {
+    Regular,
+    Bold,
+    Mono,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Self::Regular => "F1",
+            Self::Bold => "F2",
+            Self::Mono => "F3",
+        }
+    }
+}
+
+pub struct Pdf {
+    pages: Vec<Vec<u8>>,
+    current: Vec<u8>,
+    y: f32,
+}
+
+impl Default for Pdf {
+    fn default() -> Self {
+        Self {
+            pages: vec![],
+            current: vec![],
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+}
+
+impl Pdf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes a line of text below the previous one, starting a new page when needed.
+    pub fn text(&mut self, font: Font, size: f32, text: &str) {
+        let leading = size * 1.4;
+        if self.y - leading < MARGIN {
+            self.page_break();
+        }
+        self.y -= leading;
+        self.current.extend_from_slice(
+            format!(
+                "BT /{} {} Tf {} {:.2} Td (",
+                font.resource(),
+                size,
+                MARGIN,
+                self.y
+            )
+            .as_bytes(),
+        );
+        self.current.extend(encode(text));
+        self.current.extend_from_slice(b") Tj ET\n");
+    }
+
+    pub fn space(&mut self, height: f32) {
+        self.y -= height;
+    }
+
+    pub fn page_break(&mut self) {
+        let page = std::mem::take(&mut self.current);
+        self.pages.push(page);
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        if !self.current.is_empty() || self.pages.is_empty() {
+            self.page_break();
+        }
+        let fonts = ["Helvetica", "Helvetica-Bold", "Courier"];
+        let first_page = 3 + fonts.len();
+        let kids = (0..self.pages.len())
+            .map(|i| format!("{} 0 R", first_page + 2 * i))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids,
+                self.pages.len()
+            )
+            .into_bytes(),
+        ];
+        for font in fonts.iter() {
+            objects.push(
+                format!(
+                    "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                    font
+                )
+                .into_bytes(),
+            );
+        }
+        for (i, content) in self.pages.iter().enumerate() {
+            objects.push(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                     /Resources << /Font << /F1 3 0 R /F2 4 0 R /F3 5 0 R >> >> \
+                     /Contents {} 0 R >>",
+                    PAGE_WIDTH,
+                    PAGE_HEIGHT,
+                    first_page + 2 * i + 1
+                )
+                .into_bytes(),
+            );
+            let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+            stream.extend_from_slice(content);
+            stream.extend_from_slice(b"\nendstream");
+            objects.push(stream);
+        }
+
+        let mut out = b"%PDF-1.4\n".to_vec();
+        let mut offsets = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n", objects.len() + 1).as_bytes());
+        out.extend_from_slice(b"0000000000 65535 f \n");
+        for offset in offsets {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                xref
+            )
+            .as_bytes(),
+        );
+        out
+    }
+}
+
+/// Encodes text as a WinAnsi string literal body, replacing unsupported characters.
+fn encode(text: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                bytes.push(b'\\');
+                bytes.push(c as u8);
+            }
+            '€' => bytes.push(0x80),
+            c if (c as u32) >= 0x20 && (c as u32) < 0x7f => bytes.push(c as u8),
+            c if (c as u32) >= 0xa0 && (c as u32) <= 0xff => bytes.push(c as u32 as u8),
+            _ => bytes.push(b'?'),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(encode("a (b) \\ €"), b"a \\(b\\) \\\\ \x80".to_vec());
+    }
+
+    #[test]
+    fn xref_points_at_objects() {
+        let mut pdf = Pdf::new();
+        for _ in 0..100 {
+            pdf.text(Font::Regular, 10.0, "line");
+        }
+        let bytes = pdf.into_bytes();
+        let text = String::from_utf8_lossy(&bytes);
+        let startxref = text.rfind("startxref\n").unwrap() + "startxref\n".len();
+        let xref: usize = text[startxref..].lines().next().unwrap().parse().unwrap();
+        assert!(text[xref..].starts_with("xref"));
+        assert!(text.contains("/Count 2"));
+    }
+}
@@ -0,0 +1,472 @@
+//! JSON API over all entities, backed by the same `Crud` and `Filter` implementations as
+//! the command line. Lists accept the usual filter and sort directives as `f` and `s`
+//! query parameters (e.g. `/hours?f=alias::web&f=billed::no&s=ts`). Every request needs an
+//! `Authorization: Bearer <token>` header; without `--token` a token is generated at start.
+//! On loopback the `Host` header has to name loopback too, so web pages cannot reach the
+//! API through a domain that resolves to 127.0.0.1 (DNS rebinding).
+use crate::alias::{self, Alias};
+use crate::contractors::{self, Contractor};
+use crate::errors::CliError;
+use crate::generics::{Crud, Filter, Result};
+use crate::hours::{self, HourLog};
+use crate::invoice::Invoice;
+use crate::output;
+use chrono::Local;
+use colored::*;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use std::str::FromStr;
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {
+    /// Port to listen on
+    #[structopt(short = "p", long = "port", default_value = "8080")]
+    port: u16,
+    /// Address to bind to
+    #[structopt(long = "host", default_value = "127.0.0.1")]
+    host: String,
+    /// Token clients have to send as `Authorization: Bearer <token>`; a random one is
+    /// generated and printed when omitted
+    #[structopt(long = "token", env = "BOOKIT_API_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+}
+
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<CliError> for ApiError {
+    fn from(err: CliError) -> Self {
+        let status = match err {
+            CliError::Parse { .. } | CliError::CmdError(_) | CliError::Serialization(_) => 400,
+            CliError::FilterNoResults => 404,
+            CliError::Read(_)
+            | CliError::Write(_)
+            | CliError::Env(..)
+            | CliError::BinaryError(_) => 500,
+        };
+        Self::new(status, err.message())
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(400, format!("invalid JSON: {}", err))
+    }
+}
+
+type ApiResult = std::result::Result<(u16, Value), ApiError>;
+
+/// Entities exposed by the API. `from_request` builds a new record from a request body and
+/// `check_writable` refuses changes to records that have to stay as they are.
+trait Resource: Crud + Serialize + DeserializeOwned {
+    fn from_request(value: Value) -> Result<Self> {
+        Ok(serde_json::from_value(value)?)
+    }
+
+    fn check_writable(&self) -> std::result::Result<(), ApiError> {
+        Ok(())
+    }
+}
+
+impl Resource for Contractor {}
+
+impl Resource for Alias {}
+
+impl Resource for HourLog {
+    /// Hour logs get an id, timestamp and date (today) when the body does not include them.
+    fn from_request(mut value: Value) -> Result<Self> {
+        let now = Local::now().naive_local();
+        if let Some(fields) = value.as_object_mut() {
+            if !fields.contains_key("id") {
+                let existing = Self::mapping()?;
+                let id = Self::generate_id(now, |id| existing.contains_key(id))?;
+                fields.insert("id".into(), json!(id));
+            }
+            fields.entry("timestamp").or_insert_with(|| json!(now));
+            fields.entry("date").or_insert_with(|| json!(now.date()));
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Billed hours are part of an invoice; void the invoice before changing them.
+    fn check_writable(&self) -> std::result::Result<(), ApiError> {
+        if Invoice::billed_hours()?.contains(&self.id) {
+            return Err(ApiError::new(
+                409,
+                format!("{} is on an invoice and cannot be changed", self.id),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        colored::control::set_override(false);
+        let token = match &self.token {
+            Some(token) => token.clone(),
+            None => {
+                let token = generate_token();
+                output::note("Generated a token for this run; clients send it as \
+                     `Authorization: Bearer <token>` (set --token or BOOKIT_API_TOKEN to choose one)");
+                println!("{}", token);
+                token
+            }
+        };
+        let address = format!("{}:{}", self.host, self.port);
+        let server = Server::http(&address).map_err(|e| {
+            CliError::BinaryError(format!("unable to listen on {}: {}", address, e))
+        })?;
+        output::note(format!("Listening on http://{}", address));
+        for mut request in server.incoming_requests() {
+            let result = if !host_allowed(header(&request, "Host"), &self.host, self.port) {
+                Err(ApiError::new(
+                    403,
+                    "the Host header does not name this server",
+                ))
+            } else if !authorized(header(&request, "Authorization"), &token) {
+                Err(ApiError::new(401, "missing or wrong token"))
+            } else {
+                route(&mut request)
+            };
+            let (status, body) = match result {
+                Ok((status, body)) => (status, body),
+                Err(err) => (err.status, json!({ "error": err.message })),
+            };
+            output::note(format!("{} {} {}", request.method(), request.url(), status));
+            let body = match body {
+                Value::Null => String::new(),
+                body => body.to_string(),
+            };
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(
+                    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+                );
+            if let Err(err) = request.respond(response) {
+                output::note(format!("{} {}", "[Serve Warning]".yellow().bold(), err));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Random token of 32 alphanumeric characters.
+fn generate_token() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "localhost"
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+}
+
+/// Whether a server bound to `bound` should answer a request with this `Host` header. On
+/// loopback only the loopback names with the port are accepted; elsewhere the token is the
+/// only check, since the server may be reached under any name.
+fn host_allowed(header: Option<&str>, bound: &str, port: u16) -> bool {
+    if !is_loopback(bound) {
+        return true;
+    }
+    let header = match header {
+        Some(header) => header.trim().to_ascii_lowercase(),
+        None => return false,
+    };
+    ["localhost", "127.0.0.1", "[::1]"]
+        .iter()
+        .any(|name| header == format!("{}:{}", name, port))
+}
+
+/// Whether a `Content-Type` header value is JSON (parameters such as the charset aside).
+fn is_json(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|value| value.split(';').next())
+        .map(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+        .unwrap_or(false)
+}
+
+/// Whether an `Authorization` header value carries `token`.
+fn authorized(header: Option<&str>, token: &str) -> bool {
+    let given = match header.and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(given) => given.trim(),
+        None => return false,
+    };
+    // Compare every byte so the response time does not tell how much of the token matched.
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn route(request: &mut Request) -> ApiResult {
+    let url = request.url().to_string();
+    let (path, query) = match url.find('?') {
+        Some(pos) => (&url[..pos], &url[pos + 1..]),
+        None => (url.as_str(), ""),
+    };
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect::<Vec<String>>();
+    let method = request.method().clone();
+    let body = match method {
+        Method::Post | Method::Put => {
+            // a form or text body could be sent by any web page without a preflight request
+            if !is_json(header(request, "Content-Type")) {
+                return Err(ApiError::new(415, "send the body as application/json"));
+            }
+            let mut body = String::new();
+            request
+                .as_reader()
+                .read_to_string(&mut body)
+                .map_err(|e| ApiError::new(400, e.to_string()))?;
+            serde_json::from_str(&body)?
+        }
+        _ => Value::Null,
+    };
+    let id = segments.get(1).map(String::as_str);
+    if segments.len() > 2 {
+        return Err(ApiError::new(404, format!("no route for {}", path)));
+    }
+    match segments.first().map(String::as_str) {
+        Some("contractors") => {
+            handle::<Contractor, contractors::F, contractors::S>(&method, id, query, body)
+        }
+        Some("aliases") => handle::<Alias, alias::F, alias::S>(&method, id, query, body),
+        Some("hours") => handle::<HourLog, hours::F, hours::S>(&method, id, query, body),
+        _ => Err(ApiError::new(404, format!("no route for {}", path))),
+    }
+}
+
+fn handle<T, F, S>(method: &Method, id: Option<&str>, query: &str, body: Value) -> ApiResult
+where
+    T: Resource + Filter<F, S>,
+    F: FromStr<Err = CliError> + Clone,
+    S: FromStr<Err = CliError>,
+{
+    match (method, id) {
+        (Method::Get, None) => {
+            let mut filters = vec![];
+            let mut sort = T::DEFAULT_SORT;
+            for (key, value) in parse_query(query) {
+                match key.as_ref() {
+                    "f" => filters.push(F::from_str(&value)?),
+                    "s" => sort = S::from_str(&value)?,
+                    _ => return Err(ApiError::new(400, format!("unknown parameter {}", key))),
+                }
+            }
+            let items = match T::apply_filterset(T::get_base_items()?, filters) {
+                Ok(items) => T::sort(items, sort),
+                Err(CliError::FilterNoResults) => vec![],
+                Err(err) => return Err(err.into()),
+            };
+            Ok((200, serde_json::to_value(items)?))
+        }
+        (Method::Get, Some(id)) => Ok((200, serde_json::to_value(find::<T>(id)?)?)),
+        (Method::Post, None) => {
+            let obj = T::from_request(body)?;
+            obj.validate()?;
+            obj.add()?;
+            Ok((201, serde_json::to_value(obj)?))
+        }
+        (Method::Put, Some(id)) => {
+            let existing = find::<T>(id)?;
+            existing.check_writable()?;
+            let mut value = serde_json::to_value(existing)?;
+            match (value.as_object_mut(), body) {
+                (Some(fields), Value::Object(update)) => fields.extend(update),
+                _ => return Err(ApiError::new(400, "body should be a JSON object")),
+            }
+            let obj: T = serde_json::from_value(value)?;
+            if obj.identifier() != id {
+                return Err(ApiError::new(400, "identifiers cannot be changed"));
+            }
+            obj.validate()?;
+            obj.overwrite()?;
+            Ok((200, serde_json::to_value(obj)?))
+        }
+        (Method::Delete, Some(id)) => {
+            let existing = find::<T>(id)?;
+            existing.check_writable()?;
+            existing.delete()?;
+            Ok((204, Value::Null))
+        }
+        _ => Err(ApiError::new(405, "method not allowed")),
+    }
+}
+
+fn find<T: Crud>(id: &str) -> std::result::Result<T, ApiError> {
+    match T::mapping()?.remove(id) {
+        Some(obj) => Ok(obj),
+        None => Err(ApiError::new(404, format!("{} not found", id))),
+    }
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(pos) => (
+                percent_decode(&pair[..pos]),
+                percent_decode(&pair[pos + 1..]),
+            ),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+
+    #[test]
+    fn decodes_query() {
+        assert_eq!(
+            parse_query("f=alias%3A%3Aweb&s=ts&x"),
+            vec![
+                ("f".to_string(), "alias::web".to_string()),
+                ("s".to_string(), "ts".to_string()),
+                ("x".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(percent_decode("a+b%2"), "a b%2");
+    }
+
+    #[test]
+    fn requires_the_token() {
+        assert!(authorized(Some("Bearer s3cret"), "s3cret"));
+        assert!(!authorized(None, "s3cret"));
+        assert!(!authorized(Some("Bearer s3cre"), "s3cret"));
+        assert!(!authorized(Some("s3cret"), "s3cret"));
+        assert_eq!(generate_token().len(), 32);
+        assert_ne!(generate_token(), generate_token());
+    }
+
+    #[test]
+    fn loopback_servers_only_answer_to_loopback_names() {
+        assert!(is_loopback("127.0.0.1"));
+        assert!(is_loopback("[::1]"));
+        assert!(is_loopback("localhost"));
+        assert!(!is_loopback("0.0.0.0"));
+        assert!(!is_loopback("192.168.1.20"));
+        for host in [
+            "localhost:8080",
+            "127.0.0.1:8080",
+            "[::1]:8080",
+            "LOCALHOST:8080",
+        ] {
+            assert!(host_allowed(Some(host), "127.0.0.1", 8080), "{}", host);
+        }
+        for host in [
+            "evil.example:8080",
+            "localhost:9090",
+            "localhost",
+            "127.0.0.1.nip.io:8080",
+        ] {
+            assert!(!host_allowed(Some(host), "127.0.0.1", 8080), "{}", host);
+        }
+        assert!(!host_allowed(None, "localhost", 8080));
+        assert!(host_allowed(Some("books.example:8080"), "0.0.0.0", 8080));
+    }
+
+    #[test]
+    fn bodies_have_to_be_json() {
+        assert!(is_json(Some("application/json")));
+        assert!(is_json(Some("Application/JSON; charset=utf-8")));
+        assert!(!is_json(Some("text/plain")));
+        assert!(!is_json(Some("application/x-www-form-urlencoded")));
+        assert!(!is_json(None));
+    }
+
+    #[test]
+    fn refuses_changes_to_billed_hours() {
+        with_data_dir(&sample(), || {
+            let status = |id: &str| match handle::<HourLog, hours::F, hours::S>(
+                &Method::Delete,
+                Some(id),
+                "",
+                Value::Null,
+            ) {
+                Ok((status, _)) => status,
+                Err(err) => err.status,
+            };
+            assert_eq!(status("w1"), 409);
+            assert!(HourLog::mapping().unwrap().contains_key("w1"));
+            let update = handle::<HourLog, hours::F, hours::S>(
+                &Method::Put,
+                Some("w1"),
+                "",
+                json!({ "minutes": 5 }),
+            );
+            assert_eq!(update.err().map(|err| err.status), Some(409));
+            assert_eq!(status("w2"), 204);
+            assert!(!HourLog::mapping().unwrap().contains_key("w2"));
+        });
+    }
+}
@@ -0,0 +1,181 @@
+//! `bookit shell`: a prompt that takes the same commands as the command line, without the
+//! leading `bookit`. Data files are parsed once and kept in memory between commands (see
+//! `Crud::mapping`); every change is written to disk as soon as the command that made it ends.
+use crate::completions::complete_line;
+use crate::errors::CliError;
+use crate::generics::{data_dir, Result};
+use crate::output;
+use colored::*;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::path::Path;
+use std::{fs, io};
+use structopt::clap::App;
+use structopt::StructOpt;
+
+const HISTORY_FILE: &str = ".shell_history";
+
+/// Options that take secrets; lines with them are left out of the history.
+const SECRET_OPTIONS: [&str; 2] = ["--passphrase", "--token"];
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {}
+
+/// Completes commands, flags, slugs and filter directives like the shell completion scripts.
+struct Completion {
+    app: App<'static, 'static>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |space| space + 1);
+        let mut words = line[..start]
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        words.push(line[start..].to_string());
+        Ok((start, complete_line(&self.app, &words)))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+impl Cmd {
+    /// Reads lines until `exit` or end of input and hands each command to `run`, which
+    /// parses it with the grammar of `bookit` itself.
+    pub fn exec(
+        &self,
+        app: App<'static, 'static>,
+        run: fn(Vec<String>) -> Result<()>,
+    ) -> Result<()> {
+        let mut editor = Editor::<Completion, DefaultHistory>::new().map_err(readline_error)?;
+        editor.set_helper(Some(Completion { app }));
+        let history = data_dir()?.join(HISTORY_FILE);
+        create_private(&history)?;
+        let _ = editor.load_history(&history);
+        let prompt = format!("{} ", "bookit>".cyan().bold());
+        loop {
+            let line = match editor.readline(&prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(readline_error(err)),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !has_secret(line) {
+                let _ = editor.add_history_entry(line);
+            }
+            if line == "exit" || line == "quit" {
+                break;
+            }
+            let words = match shell_words::split(line) {
+                Ok(words) => words,
+                Err(err) => {
+                    eprintln!("{}", CliError::CmdError(err.to_string()));
+                    continue;
+                }
+            };
+            match run(words) {
+                Err(err) if !output::quiet() => eprintln!("{}", err),
+                _ => (),
+            }
+        }
+        editor.save_history(&history).map_err(readline_error)
+    }
+}
+
+/// Creates the history file, or restricts an existing one, so only the current user can
+/// read it; the commands in it name clients, amounts and notes.
+fn create_private(path: &Path) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .or_else(|io_err| match io_err.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(io_err),
+            })
+            .map_err(CliError::Write)?;
+    }
+    options.open(path).map_err(CliError::Write)?;
+    Ok(())
+}
+
+/// Whether a line passes a secret, as the value of one of `SECRET_OPTIONS` or of the `-p`
+/// of `site rotate`.
+fn has_secret(line: &str) -> bool {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    let rotate = words.starts_with(&["site", "rotate"]);
+    words.iter().any(|word| {
+        let option = word.split('=').next().unwrap_or_default();
+        SECRET_OPTIONS.contains(&option) || (rotate && word.starts_with("-p"))
+    })
+}
+
+fn readline_error(err: ReadlineError) -> CliError {
+    match err {
+        ReadlineError::Io(io_err) => CliError::Read(io_err),
+        err => CliError::Read(io::Error::other(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::with_data_dir;
+
+    #[test]
+    fn leaves_secrets_out_of_the_history() {
+        assert!(has_secret("site rotate -c acme --passphrase hunter2"));
+        assert!(has_secret("site rotate -c acme --passphrase=hunter2"));
+        assert!(has_secret("site rotate -c acme -p hunter2"));
+        assert!(has_secret("serve --token s3cret"));
+        assert!(!has_secret("serve -p 8081"));
+        assert!(!has_secret("site rotate -c acme"));
+        assert!(!has_secret("hours book web 30 -m \"--token rotation\""));
+    }
+
+    #[test]
+    fn only_the_user_can_read_the_history() {
+        with_data_dir(&[], || {
+            let path = data_dir().unwrap().join(HISTORY_FILE);
+            create_private(&path).unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+                assert_eq!(mode(&path), 0o600);
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+                create_private(&path).unwrap();
+                assert_eq!(mode(&path), 0o600);
+            }
+        });
+    }
+}
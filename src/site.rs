@@ -0,0 +1,619 @@
+use crate::alias::Alias;
+use crate::contractors::Contractor;
+use crate::errors::CliError;
+use crate::generics::{data_dir, Crud, Filter, Result};
+use crate::hours::HourLog;
+use crate::invoice::{format_hours, Invoice};
+use crate::money::{Money, Totals};
+use crate::output;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{Datelike, Local, NaiveDate, Weekday};
+use colored::*;
+use handlebars::Handlebars;
+use pbkdf2::pbkdf2_hmac;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// Site passphrases by contractor slug. They are kept out of the contractor records, and
+/// so out of views, exports, the API and the editor, in a file only the user can read.
+const PASSPHRASE_FILE: &str = "site_passphrases.toml";
+
+/// Iterations of PBKDF2-SHA256 used to derive page keys from the passphrase.
+const KEY_ITERATIONS: u32 = 200_000;
+
+const SHELL: &str = r#"<!DOCTYPE html>
+<html lang="{{lang}}">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<style>
+body { font-family: sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; color: #222; }
+nav a { margin-right: 1rem; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2rem; }
+th, td { text-align: left; padding: .3rem .5rem; border-bottom: 1px solid #ddd; }
+td.num, th.num { text-align: right; }
+footer { color: #888; font-size: .8rem; }
+</style>
+</head>
+<body>
+{{{content}}}
+</body>
+</html>
+"#;
+
+const LAYOUT: &str = r#"<header><h1>{{contractor.name}}</h1>
+<nav>{{#each nav}}<a href="{{file}}">{{title}}</a>{{/each}}</nav></header>
+<main>{{{body}}}</main>
+<footer>Generated on {{generated}}</footer>
+"#;
+
+/// Replaces the page content with a passphrase form. The encrypted content is decrypted
+/// in the browser with WebCrypto. The derived key is only kept in memory: links to other
+/// pages of the site are fetched and decrypted in place, and opening a page directly (or
+/// from `file://`, where pages cannot be fetched) asks for the passphrase again.
+const LOCK: &str = r#"<form id="unlock">
+<h1>Protected report</h1>
+<p><input type="password" id="passphrase" placeholder="Passphrase" autofocus> <button>Open</button></p>
+<p id="error" hidden>Wrong passphrase.</p>
+</form>
+<script type="application/json" id="payload">{{{payload}}}</script>
+<script>
+(function () {
+  var payload = JSON.parse(document.getElementById("payload").textContent);
+  var bytes = function (s) { return Uint8Array.from(atob(s), function (c) { return c.charCodeAt(0); }); };
+  var key = null;
+  function derive(passphrase) {
+    return crypto.subtle.importKey("raw", new TextEncoder().encode(passphrase), "PBKDF2", false, ["deriveKey"])
+      .then(function (base) {
+        return crypto.subtle.deriveKey(
+          { name: "PBKDF2", salt: bytes(payload.salt), iterations: payload.iterations, hash: "SHA-256" },
+          base, { name: "AES-GCM", length: 256 }, false, ["decrypt"]);
+      });
+  }
+  function show(page) {
+    return crypto.subtle.decrypt({ name: "AES-GCM", iv: bytes(page.iv) }, key, bytes(page.data))
+      .then(function (plain) { document.body.innerHTML = new TextDecoder().decode(plain); });
+  }
+  function open(href) {
+    return fetch(href)
+      .then(function (response) { return response.text(); })
+      .then(function (html) {
+        var page = new DOMParser().parseFromString(html, "text/html").getElementById("payload");
+        return show(JSON.parse(page.textContent));
+      });
+  }
+  document.addEventListener("click", function (event) {
+    var link = event.target.closest("a");
+    if (!key || !link || link.origin !== location.origin) { return; }
+    event.preventDefault();
+    open(link.href)
+      .then(function () { history.pushState(null, "", link.href); window.scrollTo(0, 0); })
+      .catch(function () { location.href = link.href; });
+  });
+  window.addEventListener("popstate", function () {
+    open(location.href).catch(function () { location.reload(); });
+  });
+  document.getElementById("unlock").addEventListener("submit", function (event) {
+    event.preventDefault();
+    derive(document.getElementById("passphrase").value)
+      .then(function (derived) { key = derived; return show(payload); })
+      .catch(function () {
+        key = null;
+        document.getElementById("error").hidden = false;
+      });
+  });
+})();
+</script>
+"#;
+
+const INDEX: &str = r#"<h2>Projects</h2>
+<table><tr><th>Alias</th><th>Description</th><th class="num">Hours</th><th class="num">Amount</th></tr>
+{{#each aliases}}<tr><td>{{slug}}</td><td>{{description}}</td><td class="num">{{hours}}</td><td class="num">{{amount}}</td></tr>
+{{/each}}</table>
+<h2>Weekly totals</h2>
+<table><tr><th>Week</th><th>From</th><th class="num">Hours</th><th class="num">Amount</th></tr>
+{{#each weeks}}<tr><td>{{week}}</td><td>{{start}}</td><td class="num">{{hours}}</td><td class="num">{{amount}}</td></tr>
+{{/each}}</table>
+"#;
+
+const HOURS: &str = r#"<h2>Hours</h2>
+<table><tr><th>Date</th><th>Alias</th><th>Ticket</th><th>Description</th><th class="num">Hours</th><th class="num">Amount</th></tr>
+{{#each hours}}<tr><td>{{date}}</td><td>{{alias}}</td><td>{{ticket}}</td><td>{{message}}</td><td class="num">{{hours}}</td><td class="num">{{amount}}</td></tr>
+{{/each}}</table>
+"#;
+
+const INVOICES: &str = r#"<h2>Invoices</h2>
+<table><tr><th>Number</th><th>Period</th><th>Issued</th><th>Due</th><th>Status</th><th class="num">Total</th></tr>
+{{#each invoices}}<tr><td>{{number}}</td><td>{{period_start}} - {{period_end}}</td><td>{{issue_date}}</td><td>{{due_date}}</td><td>{{status}}</td><td class="num">{{total}}</td></tr>
+{{/each}}</table>
+"#;
+
+#[derive(StructOpt, Debug)]
+pub enum Cmd {
+    /// Build a static HTML report site for a contractor
+    #[structopt(name = "build")]
+    Build {
+        /// Contractor to build the site for; no data of other contractors is included
+        #[structopt(short = "c", long = "contractor")]
+        contractor: String,
+        /// Directory to write the site to
+        #[structopt(short = "o", long = "out", parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Replace the passphrase that protects the site of a contractor
+    #[structopt(name = "rotate")]
+    Rotate {
+        #[structopt(short = "c", long = "contractor")]
+        contractor: String,
+        /// New passphrase (a random one is generated when omitted)
+        #[structopt(short = "p", long = "passphrase")]
+        passphrase: Option<String>,
+    },
+}
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        match self {
+            Self::Build { contractor, out } => {
+                let contractor = &Contractor::retrieve(contractor)?;
+                let passphrase = passphrase(&contractor.slug)?;
+                if passphrase.is_none() {
+                    output::note(format!(
+                        "{} {} has no site passphrase, pages are not encrypted (see 'site rotate')",
+                        "[Site Warning]".yellow().bold(),
+                        contractor.slug.yellow()
+                    ));
+                }
+                let site = SiteData::collect(contractor)?;
+                let pages = site.render(passphrase.as_deref())?;
+                write_pages(out, &pages)?;
+                output::note(format!(
+                    "Site for {} written to {} ({} pages)",
+                    contractor.slug,
+                    out.display(),
+                    pages.len()
+                ));
+            }
+            Self::Rotate {
+                contractor,
+                passphrase,
+            } => {
+                let contractor = Contractor::retrieve_exact(contractor)?;
+                let passphrase = passphrase.clone().unwrap_or_else(generate_passphrase);
+                set_passphrase(&contractor.slug, Some(&passphrase))?;
+                println!(
+                    "New passphrase for {}: {}\nRebuild the site to apply it.",
+                    contractor.slug,
+                    passphrase.bold()
+                );
+            }
+        };
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct AliasRow {
+    slug: String,
+    description: String,
+    hours: String,
+    amount: Totals,
+}
+
+#[derive(Serialize)]
+struct WeekRow {
+    week: String,
+    start: NaiveDate,
+    hours: String,
+    amount: Totals,
+}
+
+#[derive(Serialize)]
+struct HourRow {
+    date: NaiveDate,
+    alias: String,
+    ticket: Option<String>,
+    message: Option<String>,
+    hours: String,
+    amount: Money,
+}
+
+#[derive(Serialize)]
+struct InvoiceRow {
+    number: String,
+    period_start: NaiveDate,
+    period_end: NaiveDate,
+    issue_date: NaiveDate,
+    due_date: NaiveDate,
+    status: String,
+    total: Totals,
+}
+
+#[derive(Serialize)]
+pub struct SiteData {
+    contractor: Contractor,
+    generated: String,
+    aliases: Vec<AliasRow>,
+    weeks: Vec<WeekRow>,
+    hours: Vec<HourRow>,
+    invoices: Vec<InvoiceRow>,
+}
+
+pub struct Page {
+    pub file: &'static str,
+    pub html: String,
+}
+
+#[derive(Serialize)]
+struct NavItem {
+    file: &'static str,
+    title: &'static str,
+}
+
+#[derive(Serialize)]
+struct ShellData<'a> {
+    lang: &'a str,
+    title: String,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct Payload {
+    iterations: u32,
+    salt: String,
+    iv: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct LayoutData<'a> {
+    contractor: &'a Contractor,
+    generated: &'a str,
+    title: &'static str,
+    nav: &'a [NavItem],
+    body: &'a str,
+}
+
+impl SiteData {
+    /// Collects everything on the site, restricted to the aliases of a single contractor.
+    pub fn collect(contractor: &Contractor) -> Result<Self> {
+        let aliases = Alias::get_default_items(None, None)?
+            .into_iter()
+            .filter(|alias| alias.contractor == contractor.slug)
+            .map(|alias| (alias.identifier(), alias))
+            .collect::<HashMap<String, Alias>>();
+        let mut logs = HourLog::get_default_items(None, None)?
+            .into_iter()
+            .filter(|log| aliases.contains_key(&log.alias))
+            .collect::<Vec<HourLog>>();
+        logs.sort_by_key(|log| std::cmp::Reverse((log.date, log.timestamp)));
+
+        let mut per_alias: BTreeMap<&str, (u32, Totals)> = BTreeMap::new();
+        let mut per_week: BTreeMap<(i32, u32), (u32, Totals)> = BTreeMap::new();
+        let mut hours = vec![];
+        for log in &logs {
+            let alias = &aliases[&log.alias];
+            let amount = log.amount(alias);
+            let week = log.date.iso_week();
+            for (minutes, total) in [
+                per_alias.entry(&alias.slug).or_default(),
+                per_week.entry((week.year(), week.week())).or_default(),
+            ] {
+                *minutes += log.minutes;
+                total.add(&amount);
+            }
+            hours.push(HourRow {
+                date: log.date,
+                alias: log.alias.clone(),
+                ticket: log.ticket.clone(),
+                message: log.message.clone(),
+                hours: format_hours(log.minutes),
+                amount,
+            });
+        }
+
+        let mut alias_rows = aliases
+            .values()
+            .map(|alias| {
+                let (minutes, amount) = per_alias.remove(alias.slug.as_str()).unwrap_or_default();
+                AliasRow {
+                    slug: alias.slug.clone(),
+                    description: alias.short_description.clone(),
+                    hours: format_hours(minutes),
+                    amount,
+                }
+            })
+            .collect::<Vec<AliasRow>>();
+        alias_rows.sort_by(|a, b| a.slug.cmp(&b.slug));
+
+        let weeks = per_week
+            .into_iter()
+            .rev()
+            .map(|((year, week), (minutes, amount))| WeekRow {
+                week: format!("{}-W{:02}", year, week),
+                start: NaiveDate::from_isoywd(year, week, Weekday::Mon),
+                hours: format_hours(minutes),
+                amount,
+            })
+            .collect();
+
+        let mut invoices = Invoice::get_default_items(None, None)?
+            .into_iter()
+            .filter(|invoice| invoice.contractor == contractor.slug)
+            .collect::<Vec<Invoice>>();
+        invoices.sort_by_key(|invoice| std::cmp::Reverse((invoice.issue_date, invoice.sequence)));
+        let invoices = invoices
+            .into_iter()
+            .map(|invoice| {
+                let mut total = Totals::default();
+                for amount in &invoice.total {
+                    total.add(amount);
+                }
+                InvoiceRow {
+                    number: invoice.number,
+                    period_start: invoice.period_start,
+                    period_end: invoice.period_end,
+                    issue_date: invoice.issue_date,
+                    due_date: invoice.due_date,
+                    status: invoice.status.to_string(),
+                    total,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            contractor: contractor.clone(),
+            generated: Local::now()
+                .naive_local()
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            aliases: alias_rows,
+            weeks,
+            hours,
+            invoices,
+        })
+    }
+
+    /// Renders the body of every page followed by the shared layout. With a passphrase
+    /// the content of each page is encrypted and only a decryption form is readable.
+    pub fn render(&self, passphrase: Option<&str>) -> Result<Vec<Page>> {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        let bodies = [
+            ("index.html", "Overview", INDEX),
+            ("hours.html", "Hours", HOURS),
+            ("invoices.html", "Invoices", INVOICES),
+        ];
+        let nav = bodies
+            .iter()
+            .map(|(file, title, _)| NavItem { file, title })
+            .collect::<Vec<NavItem>>();
+        let cipher = passphrase.map(PageCipher::new).transpose()?;
+        let mut pages = vec![];
+        for (file, title, template) in bodies {
+            let body = handlebars.render_template(template, self)?;
+            let content = handlebars.render_template(
+                LAYOUT,
+                &LayoutData {
+                    contractor: &self.contractor,
+                    generated: &self.generated,
+                    title,
+                    nav: &nav,
+                    body: &body,
+                },
+            )?;
+            let (title, content) = match &cipher {
+                Some(cipher) => {
+                    let payload = serde_json::to_string(&cipher.encrypt(&content)?)?;
+                    let lock = handlebars.render_template(LOCK, &json!({ "payload": payload }))?;
+                    ("Protected report".to_string(), lock)
+                }
+                None => (format!("{} - {}", title, self.contractor.name), content),
+            };
+            let html = handlebars.render_template(
+                SHELL,
+                &ShellData {
+                    lang: &self.contractor.language,
+                    title,
+                    content: &content,
+                },
+            )?;
+            pages.push(Page { file, html });
+        }
+        Ok(pages)
+    }
+}
+
+/// AES-256-GCM cipher keyed from the passphrase; the key is derived once per build and
+/// every page is encrypted with its own random nonce.
+struct PageCipher {
+    cipher: Aes256Gcm,
+    salt: [u8; 16],
+}
+
+impl PageCipher {
+    fn new(passphrase: &str) -> Result<Self> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let mut key = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, KEY_ITERATIONS, &mut key);
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| CliError::BinaryError("invalid encryption key length".into()))?;
+        Ok(Self { cipher, salt })
+    }
+
+    fn encrypt(&self, content: &str) -> Result<Payload> {
+        let mut iv = [0u8; 12];
+        OsRng.fill_bytes(&mut iv);
+        let data = self
+            .cipher
+            .encrypt(Nonce::from_slice(&iv), content.as_bytes())
+            .map_err(|_| CliError::BinaryError("unable to encrypt page".into()))?;
+        Ok(Payload {
+            iterations: KEY_ITERATIONS,
+            salt: BASE64.encode(self.salt),
+            iv: BASE64.encode(iv),
+            data: BASE64.encode(data),
+        })
+    }
+}
+
+fn passphrases() -> Result<BTreeMap<String, String>> {
+    match fs::read_to_string(data_dir()?.join(PASSPHRASE_FILE)) {
+        Ok(content) => Ok(toml::from_str(&content)?),
+        Err(io_err) if io_err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(io_err) => Err(CliError::Read(io_err)),
+    }
+}
+
+fn write_passphrases(passphrases: &BTreeMap<String, String>) -> Result<()> {
+    let path = data_dir()?.join(PASSPHRASE_FILE);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // the mode only applies to new files
+        if path.exists() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                .map_err(CliError::Write)?;
+        }
+    }
+    let content = toml::to_string(passphrases)?;
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(CliError::Write)
+}
+
+/// Passphrase protecting the site of a contractor, if it has one.
+pub fn passphrase(contractor: &str) -> Result<Option<String>> {
+    Ok(passphrases()?.remove(contractor))
+}
+
+/// Sets or, with `None`, removes the passphrase of a contractor.
+pub fn set_passphrase(contractor: &str, passphrase: Option<&str>) -> Result<()> {
+    let mut passphrases = passphrases()?;
+    let changed = match passphrase {
+        Some(passphrase) => {
+            passphrases.insert(contractor.to_string(), passphrase.to_string());
+            true
+        }
+        None => passphrases.remove(contractor).is_some(),
+    };
+    match changed {
+        true => write_passphrases(&passphrases),
+        false => Ok(()),
+    }
+}
+
+/// Hands the passphrase of contractor `old` to `new` (renamed or merged into) unless `new`
+/// has one of its own.
+pub fn move_passphrase(old: &str, new: &str) -> Result<()> {
+    let mut passphrases = passphrases()?;
+    match passphrases.remove(old) {
+        Some(passphrase) => {
+            passphrases.entry(new.to_string()).or_insert(passphrase);
+            write_passphrases(&passphrases)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Random passphrase of 24 alphanumeric characters.
+pub fn generate_passphrase() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect()
+}
+
+fn write_pages(out: &Path, pages: &[Page]) -> Result<()> {
+    fs::create_dir_all(out).map_err(CliError::Write)?;
+    for page in pages {
+        fs::write(out.join(page.file), &page.html).map_err(CliError::Write)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+
+    #[test]
+    fn passphrases_stay_out_of_contractor_records() {
+        with_data_dir(&sample(), || {
+            set_passphrase("acme", Some("hunter2")).unwrap();
+            assert_eq!(passphrase("acme").unwrap().as_deref(), Some("hunter2"));
+            let record = fs::read_to_string(Contractor::path().unwrap()).unwrap();
+            assert!(!record.contains("hunter2"));
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let file = data_dir().unwrap().join(PASSPHRASE_FILE);
+                let mode = fs::metadata(file).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+            move_passphrase("acme", "acme-corp").unwrap();
+            assert_eq!(passphrase("acme").unwrap(), None);
+            assert_eq!(passphrase("acme-corp").unwrap().as_deref(), Some("hunter2"));
+        });
+    }
+
+    #[test]
+    fn site_only_holds_the_contractor_data() {
+        let (site, pages) = with_data_dir(&sample(), || {
+            let site = SiteData::collect(&Contractor::retrieve("acme").unwrap()).unwrap();
+            let pages = site.render(None).unwrap();
+            (site, pages)
+        });
+        let slugs = site.aliases.iter().map(|row| row.slug.as_str());
+        assert_eq!(slugs.collect::<Vec<&str>>(), vec!["api", "web"]);
+        let hours = site.hours.iter().map(|row| row.alias.as_str());
+        assert_eq!(hours.collect::<Vec<&str>>(), vec!["api", "web", "web"]);
+        let invoices = site.invoices.iter().map(|row| row.number.as_str());
+        assert_eq!(invoices.collect::<Vec<&str>>(), vec!["INV-2024-0001"]);
+        assert_eq!(site.aliases[1].amount.to_string(), "150.00 EUR");
+        assert_eq!(pages.len(), 3);
+        for page in &pages {
+            for other in ["globex", "Globex", "shop", "Checkout", "INV-2024-0002"] {
+                assert!(!page.html.contains(other), "{} on {}", other, page.file);
+            }
+        }
+        assert!(pages[1].html.contains("Landing page"));
+    }
+
+    #[test]
+    fn encrypted_payload_decrypts_with_passphrase() {
+        let payload = PageCipher::new("secret")
+            .unwrap()
+            .encrypt("<main>hours</main>")
+            .unwrap();
+        let mut key = [0u8; 32];
+        let salt = BASE64.decode(&payload.salt).unwrap();
+        pbkdf2_hmac::<Sha256>(b"secret", &salt, payload.iterations, &mut key);
+        let cipher = Aes256Gcm::new_from_slice(&key).unwrap();
+        let iv = BASE64.decode(&payload.iv).unwrap();
+        let data = BASE64.decode(&payload.data).unwrap();
+        let plain = cipher
+            .decrypt(Nonce::from_slice(&iv), data.as_ref())
+            .unwrap();
+        assert_eq!(plain, b"<main>hours</main>");
+    }
+}
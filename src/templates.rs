@@ -0,0 +1,195 @@
+//! Rendering of user supplied invoice templates. Templates are Handlebars files in
+//! `$BOOKIT_DIR/templates/` and are referenced by file name from a contractor record.
+//! The rendered HTML is converted to PDF with an external command (see `html_to_pdf`).
+use crate::alias::Alias;
+use crate::contractors::Contractor;
+use crate::errors::CliError;
+use crate::generics::{data_dir, private_temp_file, Result};
+use crate::hours::HourLog;
+use crate::invoice::{format_hours, total, Invoice};
+use crate::money::Money;
+use handlebars::Handlebars;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+const DEFAULT_PDF_COMMAND: &str = "wkhtmltopdf --quiet {input} {output}";
+
+/// Data available to invoice templates; see the README for a description of every field.
+#[derive(Serialize)]
+pub struct InvoiceData<'a> {
+    invoice: &'a Invoice,
+    contractor: &'a Contractor,
+    aliases: Vec<&'a Alias>,
+    lines: Vec<LineData<'a>>,
+    hours: Vec<HourData<'a>>,
+    totals: TotalsData,
+}
+
+#[derive(Serialize)]
+struct LineData<'a> {
+    description: &'a str,
+    minutes: u32,
+    hours: String,
+    rate: &'a Money,
+    amount: &'a Money,
+}
+
+#[derive(Serialize)]
+struct HourData<'a> {
+    #[serde(flatten)]
+    log: &'a HourLog,
+    hours: String,
+}
+
+#[derive(Serialize)]
+struct TotalsData {
+    minutes: u32,
+    hours: String,
+    amounts: Vec<Money>,
+}
+
+impl<'a> InvoiceData<'a> {
+    pub fn new(
+        invoice: &'a Invoice,
+        contractor: &'a Contractor,
+        aliases: &'a HashMap<String, Alias>,
+        logs: &'a [HourLog],
+    ) -> Self {
+        let mut used_aliases = aliases
+            .values()
+            .filter(|alias| logs.iter().any(|log| log.alias == alias.slug))
+            .collect::<Vec<&Alias>>();
+        used_aliases.sort_by(|a, b| a.slug.cmp(&b.slug));
+        let hours = logs
+            .iter()
+            .map(|log| HourData {
+                log,
+                hours: format_hours(log.minutes),
+            })
+            .collect();
+        let lines = &invoice.lines;
+        let minutes = lines.iter().map(|line| line.minutes).sum();
+        Self {
+            invoice,
+            contractor,
+            aliases: used_aliases,
+            lines: lines
+                .iter()
+                .map(|line| LineData {
+                    description: &line.description,
+                    minutes: line.minutes,
+                    hours: format_hours(line.minutes),
+                    rate: &line.rate,
+                    amount: &line.amount,
+                })
+                .collect(),
+            hours,
+            totals: TotalsData {
+                minutes,
+                hours: format_hours(minutes),
+                amounts: total(lines).amounts(),
+            },
+        }
+    }
+}
+
+pub fn template_path(name: &str) -> Result<PathBuf> {
+    Ok(data_dir()?.join("templates").join(name))
+}
+
+pub fn render_html(template_name: &str, data: &InvoiceData) -> Result<String> {
+    let template = fs::read_to_string(template_path(template_name)?).map_err(CliError::Read)?;
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    Ok(handlebars.render_template(&template, data)?)
+}
+
+/// Converts HTML to PDF with the command in `BOOKIT_PDF_COMMAND`, in which `{input}` and
+/// `{output}` are replaced with the paths of the HTML and PDF files.
+pub fn html_to_pdf(html: &str, out: &Path) -> Result<()> {
+    let input = private_temp_file("bookit-invoice", "html", html)?;
+    let command = env::var("BOOKIT_PDF_COMMAND").unwrap_or_else(|_| DEFAULT_PDF_COMMAND.into());
+    let args = command
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &out.to_string_lossy())
+        })
+        .collect::<Vec<String>>();
+    let result = match args.split_first() {
+        Some((program, args)) => Command::new(program).args(args).status(),
+        None => {
+            return Err(CliError::BinaryError(
+                "BOOKIT_PDF_COMMAND is empty".to_string(),
+            ))
+        }
+    };
+    let _ = fs::remove_file(&input);
+    match result {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(CliError::BinaryError(format!(
+            "'{}' failed with {}",
+            command, status
+        ))),
+        Err(io_err) => Err(CliError::BinaryError(format!(
+            "unable to run '{}': {}",
+            command, io_err
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+    use crate::generics::Crud;
+
+    const TEMPLATE: &str = "{{invoice.number}} for {{contractor.name}}
+{{#each lines}}{{description}}: {{hours}} h x {{rate}} = {{amount}}
+{{/each}}{{#each hours}}{{id}} {{date}} {{hours}}
+{{/each}}Total {{totals.hours}} h {{#each totals.amounts}}{{this}}{{/each}}";
+
+    fn render(template: &str) -> Result<String> {
+        let invoice = Invoice::retrieve("INV-2024-0001")?;
+        let contractor = Contractor::retrieve(&invoice.contractor)?;
+        let aliases = Alias::mapping()?;
+        let logs = invoice.hour_logs()?;
+        let data = InvoiceData::new(&invoice, &contractor, &aliases, &logs);
+        render_html(template, &data)
+    }
+
+    #[test]
+    fn renders_invoice_templates() {
+        let mut files = sample().to_vec();
+        files.push(("templates/invoice.hbs", TEMPLATE));
+        files.push(("templates/typo.hbs", "{{invoice.numbr}}"));
+        with_data_dir(&files, || {
+            assert_eq!(
+                render("invoice.hbs").unwrap(),
+                "INV-2024-0001 for Acme\nWebsite: 1.00 h x 90.00 EUR = 90.00 EUR\n\
+                 w1 2024-03-04 1.00\nTotal 1.00 h 90.00 EUR"
+            );
+            // strict mode turns unknown fields into errors instead of empty text
+            assert!(render("typo.hbs").is_err());
+            assert!(render("missing.hbs").is_err());
+        });
+    }
+
+    #[test]
+    fn converts_html_with_the_configured_command() {
+        with_data_dir(&[], || {
+            let out = data_dir().unwrap().join("invoice.pdf");
+            env::set_var("BOOKIT_PDF_COMMAND", "cp {input} {output}");
+            let copied = html_to_pdf("<p>invoice</p>", &out);
+            env::set_var("BOOKIT_PDF_COMMAND", "false {input}");
+            let failed = html_to_pdf("<p>invoice</p>", &out);
+            env::remove_var("BOOKIT_PDF_COMMAND");
+            copied.unwrap();
+            assert_eq!(fs::read_to_string(&out).unwrap(), "<p>invoice</p>");
+            assert!(matches!(failed, Err(CliError::BinaryError(_))));
+        });
+    }
+}
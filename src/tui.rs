@@ -0,0 +1,539 @@
+//! `bookit tui`: a full-screen dashboard with the bookings of the week, today's and this
+//! week's totals, totals per alias and a timer that books the elapsed time when stopped.
+//! Bookings are read, filtered and written through `Crud` and `Filter` like the commands,
+//! so every edit is on disk as soon as it is confirmed.
+use crate::alias::Alias;
+use crate::errors::CliError;
+use crate::generics::{plain_list_items, Crud, Filter, Result};
+use crate::hours::{self, interpret_time, HourLog};
+use crate::invoice::{format_hours, Invoice};
+use crate::utils::interactive;
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration as Tick, Instant};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct Cmd {}
+
+/// What the input line at the bottom is asking for.
+#[derive(Clone, Copy, PartialEq)]
+enum Prompt {
+    Filter,
+    Message,
+    Ticket,
+    Time,
+    Alias,
+    Timer,
+}
+
+impl Prompt {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Filter => "filters",
+            Self::Message => "message",
+            Self::Ticket => "ticket",
+            Self::Time => "time",
+            Self::Alias => "alias",
+            Self::Timer => "start timer on alias",
+        }
+    }
+}
+
+/// Prompts that change a booking hold its id, so a reload while the prompt is open cannot
+/// point them at another booking.
+enum Mode {
+    Browse,
+    Input(Prompt, String, Option<String>),
+    ConfirmDelete(String),
+    ConfirmQuit,
+}
+
+struct Timer {
+    alias: String,
+    started: NaiveDateTime,
+}
+
+struct Dashboard {
+    /// All bookings of the current week, newest first
+    week: Vec<HourLog>,
+    /// The bookings shown in the list: the week or everything, with the filters applied
+    visible: Vec<HourLog>,
+    filters: Vec<String>,
+    everything: bool,
+    list: ListState,
+    mode: Mode,
+    timer: Option<Timer>,
+    status: String,
+    loaded: Instant,
+}
+
+const HELP: &str = "↑↓ scroll  / filter  a all/week  e message  t ticket  m time  l alias  d delete  s timer  q quit";
+
+impl Cmd {
+    pub fn exec(&self) -> Result<()> {
+        if !interactive() {
+            return Err(CliError::CmdError("the dashboard needs a terminal".into()));
+        }
+        let mut dashboard = Dashboard::new()?;
+        let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+        // errors are shown in the status line, without escape codes
+        colored::control::set_override(false);
+        let mut terminal = ratatui::try_init().map_err(CliError::Write)?;
+        let result = dashboard.run(&mut terminal);
+        ratatui::restore();
+        colored::control::set_override(colorize);
+        result
+    }
+}
+
+impl Dashboard {
+    fn new() -> Result<Self> {
+        let mut dashboard = Self {
+            week: vec![],
+            visible: vec![],
+            filters: vec![],
+            everything: false,
+            list: ListState::default(),
+            mode: Mode::Browse,
+            timer: None,
+            status: String::new(),
+            loaded: Instant::now(),
+        };
+        dashboard.reload()?;
+        dashboard.list.select_first();
+        Ok(dashboard)
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .map_err(CliError::Write)?;
+            if event::poll(Tick::from_millis(250)).map_err(CliError::Read)? {
+                if let Event::Key(key) = event::read().map_err(CliError::Read)? {
+                    if key.kind == KeyEventKind::Press && !self.handle(key) {
+                        return Ok(());
+                    }
+                }
+            }
+            // pick up bookings made elsewhere, e.g. from another terminal, but keep the list
+            // still while a prompt is open
+            if matches!(self.mode, Mode::Browse) && self.loaded.elapsed() > Tick::from_secs(2) {
+                self.refresh();
+            }
+        }
+    }
+
+    /// Reads the bookings again and applies the scope and filters to the list.
+    fn reload(&mut self) -> Result<()> {
+        self.loaded = Instant::now();
+        let monday = week_start(today());
+        let logs = HourLog::sort(HourLog::get_base_items()?, hours::S::ByTimestamp);
+        self.week = logs
+            .iter()
+            .filter(|log| log.date >= monday)
+            .cloned()
+            .collect();
+        let scope = if self.everything {
+            logs
+        } else {
+            self.week.clone()
+        };
+        let filters = self
+            .filters
+            .iter()
+            .map(|filter| hours::F::from_str(filter))
+            .collect::<Result<Vec<hours::F>>>()?;
+        self.visible = match HourLog::apply_filterset(scope, filters) {
+            Err(CliError::FilterNoResults) => vec![],
+            visible => visible?,
+        };
+        if self.visible.is_empty() {
+            self.list.select(None);
+        } else if self.list.selected().is_none_or(|i| i >= self.visible.len()) {
+            self.list.select_last();
+        }
+        Ok(())
+    }
+
+    fn refresh(&mut self) {
+        if let Err(err) = self.reload() {
+            self.status = err.message();
+        }
+    }
+
+    fn selected(&self) -> Option<&HourLog> {
+        self.list.selected().and_then(|i| self.visible.get(i))
+    }
+
+    /// Handles a key press; returns false when the dashboard should close.
+    fn handle(&mut self, key: KeyEvent) -> bool {
+        let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+        match mode {
+            Mode::Input(prompt, mut text, id) => match key.code {
+                KeyCode::Esc => self.status.clear(),
+                KeyCode::Enter => {
+                    self.status = match self.submit(prompt, text.trim(), id.as_deref()) {
+                        Ok(status) => status,
+                        Err(err) => err.message(),
+                    };
+                    self.refresh();
+                }
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.mode = Mode::Input(prompt, text, id);
+                }
+                KeyCode::Char(c) => {
+                    text.push(c);
+                    self.mode = Mode::Input(prompt, text, id);
+                }
+                _ => self.mode = Mode::Input(prompt, text, id),
+            },
+            Mode::ConfirmDelete(id) => {
+                self.status = match key.code {
+                    KeyCode::Char('y') => match booking(&id).and_then(|log| log.delete()) {
+                        Ok(()) => format!("Deleted {}", id),
+                        Err(err) => err.message(),
+                    },
+                    _ => String::new(),
+                };
+                self.refresh();
+            }
+            Mode::ConfirmQuit => match key.code {
+                KeyCode::Char('q') => return false,
+                _ => {
+                    self.status.clear();
+                    return self.browse(key);
+                }
+            },
+            Mode::Browse => return self.browse(key),
+        }
+        true
+    }
+
+    fn browse(&mut self, key: KeyEvent) -> bool {
+        let edit = |prompt: Prompt, value: Option<String>, id: Option<&String>| {
+            Mode::Input(prompt, value.unwrap_or_default(), id.cloned())
+        };
+        let selected = self.selected().cloned();
+        self.mode = match (key.code, selected) {
+            (KeyCode::Char('q') | KeyCode::Esc, _) if self.timer.is_some() => {
+                self.status =
+                    "The timer is running: press s to book it or q to quit without booking".into();
+                Mode::ConfirmQuit
+            }
+            (KeyCode::Char('q') | KeyCode::Esc, _) => return false,
+            (KeyCode::Down | KeyCode::Char('j'), _) => {
+                self.list.select_next();
+                Mode::Browse
+            }
+            (KeyCode::Up | KeyCode::Char('k'), _) => {
+                self.list.select_previous();
+                Mode::Browse
+            }
+            (KeyCode::Char('/'), _) => edit(Prompt::Filter, Some(self.filters.join(" ")), None),
+            (KeyCode::Char('a'), _) => {
+                self.everything = !self.everything;
+                self.refresh();
+                Mode::Browse
+            }
+            (KeyCode::Char('s'), _) => match self.timer.take() {
+                Some(timer) => {
+                    self.status = match book_timer(timer) {
+                        Ok(status) => status,
+                        Err(err) => err.message(),
+                    };
+                    self.refresh();
+                    self.list.select_first();
+                    Mode::Browse
+                }
+                None => edit(Prompt::Timer, None, None),
+            },
+            (KeyCode::Char('e'), Some(log)) => edit(Prompt::Message, log.message, Some(&log.id)),
+            (KeyCode::Char('t'), Some(log)) => edit(Prompt::Ticket, log.ticket, Some(&log.id)),
+            (KeyCode::Char('m'), Some(log)) => {
+                edit(Prompt::Time, Some(log.minutes.to_string()), Some(&log.id))
+            }
+            (KeyCode::Char('l'), Some(log)) => edit(Prompt::Alias, Some(log.alias), Some(&log.id)),
+            (KeyCode::Char('d'), Some(log)) => {
+                self.status = format!("Delete {}? (y/n)", log.id);
+                Mode::ConfirmDelete(log.id)
+            }
+            _ => Mode::Browse,
+        };
+        true
+    }
+
+    /// Applies the input line to the booking with `id`; the returned message goes in the
+    /// status line.
+    fn submit(&mut self, prompt: Prompt, text: &str, id: Option<&str>) -> Result<String> {
+        let optional = || Some(text.to_string()).filter(|text| !text.is_empty());
+        if prompt == Prompt::Filter {
+            let filters = text
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<String>>();
+            for filter in &filters {
+                hours::F::from_str(filter)?;
+            }
+            self.filters = filters;
+            return Ok(String::new());
+        }
+        if prompt == Prompt::Timer {
+            let alias = Alias::retrieve(text)?;
+            let status = format!("Timer started on {}", alias.slug);
+            self.timer = Some(Timer {
+                alias: alias.slug,
+                started: Local::now().naive_local(),
+            });
+            return Ok(status);
+        }
+        let mut log = match id {
+            Some(id) => booking(id)?,
+            None => return Ok(String::new()),
+        };
+        match prompt {
+            Prompt::Message => log.message = optional(),
+            Prompt::Ticket => log.ticket = optional(),
+            Prompt::Time => log.minutes = interpret_time(text)?,
+            Prompt::Alias => log.alias = Alias::retrieve_exact(text)?.slug,
+            Prompt::Filter | Prompt::Timer => (),
+        }
+        log.validate()?;
+        log.overwrite()?;
+        Ok(format!("Updated {}", log.id))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [totals, main, status, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [today_area, week_area, timer_area] = Layout::horizontal([
+            Constraint::Ratio(1, 3),
+            Constraint::Ratio(1, 3),
+            Constraint::Ratio(1, 3),
+        ])
+        .areas(totals);
+        let [list_area, aliases_area] =
+            Layout::horizontal([Constraint::Min(40), Constraint::Length(28)]).areas(main);
+
+        let today = today();
+        let minutes =
+            |logs: &mut dyn Iterator<Item = &HourLog>| logs.map(|log| log.minutes).sum::<u32>();
+        let today_minutes = minutes(&mut self.week.iter().filter(|log| log.date == today));
+        let week_minutes = minutes(&mut self.week.iter());
+        frame.render_widget(total("Today", today_minutes), today_area);
+        frame.render_widget(total("This week", week_minutes), week_area);
+        let timer = match &self.timer {
+            Some(timer) => {
+                let elapsed = (Local::now().naive_local() - timer.started)
+                    .num_seconds()
+                    .max(0);
+                format!(
+                    "{} {:02}:{:02}:{:02}",
+                    timer.alias,
+                    elapsed / 3600,
+                    elapsed / 60 % 60,
+                    elapsed % 60
+                )
+            }
+            None => "not running".into(),
+        };
+        frame.render_widget(
+            Paragraph::new(timer).block(Block::bordered().title("Timer")),
+            timer_area,
+        );
+
+        let labels = plain_list_items(&self.visible);
+        let items = self
+            .visible
+            .iter()
+            .zip(labels)
+            .map(|(log, label)| {
+                let line = Line::from(format!("{} {}", log.date, label));
+                if log.date == today {
+                    line.bold()
+                } else {
+                    line
+                }
+            })
+            .collect::<Vec<Line>>();
+        let mut title = String::from(if self.everything {
+            "All bookings"
+        } else {
+            "This week"
+        });
+        if !self.filters.is_empty() {
+            title = format!("{} [{}]", title, self.filters.join(" "));
+        }
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().reversed())
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        let mut per_alias = BTreeMap::new();
+        for log in &self.week {
+            *per_alias.entry(log.alias.as_str()).or_insert(0) += log.minutes;
+        }
+        let aliases = per_alias
+            .iter()
+            .map(|(alias, minutes)| {
+                Line::from(format!("{:<18} {:>7}", alias, format_hours(*minutes)))
+            })
+            .collect::<Vec<Line>>();
+        frame.render_widget(
+            Paragraph::new(aliases).block(Block::bordered().title("Hours per alias")),
+            aliases_area,
+        );
+
+        match &self.mode {
+            Mode::Input(prompt, text, _) => {
+                let input = format!("{}: {}", prompt.label(), text);
+                let cursor = input.chars().count() as u16;
+                frame.render_widget(Paragraph::new(input), status);
+                frame.set_cursor_position((status.x + cursor.min(status.width), status.y));
+            }
+            _ => frame.render_widget(Paragraph::new(self.status.as_str()).yellow(), status),
+        }
+        frame.render_widget(Paragraph::new(HELP).dim(), help);
+    }
+}
+
+fn total(title: &str, minutes: u32) -> Paragraph<'static> {
+    Paragraph::new(format!("{} hours", format_hours(minutes)))
+        .bold()
+        .block(Block::bordered().title(title.to_string()))
+}
+
+/// Books the time since the timer was started on its alias.
+fn book_timer(timer: Timer) -> Result<String> {
+    let now = Local::now().naive_local();
+    let minutes = (now - timer.started).num_minutes();
+    if minutes < 1 {
+        return Ok("Timer stopped after less than a minute, nothing was booked".into());
+    }
+    let existing = HourLog::mapping()?;
+    let log = HourLog {
+        alias: timer.alias,
+        minutes: minutes as u32,
+        date: timer.started.date(),
+        message: None,
+        ticket: None,
+        branch: None,
+        id: HourLog::generate_id(now, |id| existing.contains_key(id))?,
+        timestamp: now,
+    };
+    log.validate()?;
+    log.add()?;
+    Ok(format!(
+        "Booked {} minutes on {} ({}), press e to add a message",
+        log.minutes, log.alias, log.id
+    ))
+}
+
+/// The booking with `id` to change or delete. It may have been deleted elsewhere since it
+/// was selected, and bookings on an invoice stay as they are, like with `hours delete`.
+fn booking(id: &str) -> Result<HourLog> {
+    let log = HourLog::mapping()?
+        .remove(id)
+        .ok_or_else(|| CliError::CmdError(format!("{} no longer exists", id)))?;
+    match Invoice::billed_hours()?.contains(id) {
+        true => Err(CliError::CmdError(format!(
+            "{} is on an invoice and cannot be changed, void the invoice first",
+            id
+        ))),
+        false => Ok(log),
+    }
+}
+
+fn today() -> NaiveDate {
+    Local::now().naive_local().date()
+}
+
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(i64::from(date.weekday().num_days_from_monday()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::testing::{sample, with_data_dir};
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let monday = NaiveDate::from_ymd(2024, 3, 4);
+        assert_eq!(week_start(monday), monday);
+        assert_eq!(week_start(NaiveDate::from_ymd(2024, 3, 6)), monday);
+        assert_eq!(week_start(NaiveDate::from_ymd(2024, 3, 10)), monday);
+    }
+
+    #[test]
+    fn timers_book_whole_minutes() {
+        with_data_dir(&sample(), || {
+            let timer = |seconds| Timer {
+                alias: "web".into(),
+                started: Local::now().naive_local() - Duration::seconds(seconds),
+            };
+            let status = book_timer(timer(30)).unwrap();
+            assert!(status.contains("nothing was booked"));
+            assert_eq!(HourLog::mapping().unwrap().len(), 4);
+            book_timer(timer(5 * 60 + 10)).unwrap();
+            let logs = HourLog::mapping().unwrap();
+            assert_eq!(logs.len(), 5);
+            assert!(logs
+                .values()
+                .any(|log| log.alias == "web" && log.minutes == 5));
+        });
+    }
+
+    #[test]
+    fn prompts_change_the_booking_they_were_opened_for() {
+        with_data_dir(&sample(), || {
+            let mut dashboard = Dashboard::new().unwrap();
+            dashboard.submit(Prompt::Ticket, "T-9", Some("w2")).unwrap();
+            dashboard.submit(Prompt::Time, "h::1", Some("w2")).unwrap();
+            let w2 = booking("w2").unwrap();
+            assert_eq!(w2.ticket.as_deref(), Some("T-9"));
+            assert_eq!(w2.minutes, 60);
+            // changing the alias needs the full slug
+            assert!(dashboard.submit(Prompt::Alias, "ap", Some("a1")).is_err());
+            dashboard.submit(Prompt::Alias, "web", Some("a1")).unwrap();
+            assert_eq!(booking("a1").unwrap().alias, "web");
+            assert!(dashboard
+                .submit(Prompt::Message, "x", Some("gone"))
+                .is_err());
+            assert!(dashboard.submit(Prompt::Filter, "nope::x", None).is_err());
+
+            // s1 is on a draft invoice, w2 is not billed
+            dashboard.mode = Mode::ConfirmDelete("s1".into());
+            assert!(dashboard.handle(KeyEvent::from(KeyCode::Char('y'))));
+            assert!(dashboard.status.contains("s1 is on an invoice"));
+            assert!(HourLog::mapping().unwrap().contains_key("s1"));
+            dashboard.mode = Mode::Input(Prompt::Message, "x".into(), Some("s1".into()));
+            assert!(dashboard.handle(KeyEvent::from(KeyCode::Enter)));
+            assert!(dashboard.status.contains("s1 is on an invoice"));
+            assert_eq!(
+                HourLog::mapping().unwrap()["s1"].message.as_deref(),
+                Some("Checkout")
+            );
+
+            dashboard.mode = Mode::ConfirmDelete("w2".into());
+            assert!(dashboard.handle(KeyEvent::from(KeyCode::Char('y'))));
+            assert_eq!(dashboard.status, "Deleted w2");
+            assert!(!HourLog::mapping().unwrap().contains_key("w2"));
+        });
+    }
+}
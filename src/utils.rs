@@ -1,6 +1,8 @@
 use crate::errors::CliError;
 use crate::generics::Result;
 use chrono::{Local as LocalTime, NaiveDate, NaiveTime, Weekday, Datelike};
+use colored::*;
+use std::io::{self, IsTerminal};
 use std::str::FromStr;
 
 enum UtilError {
@@ -21,6 +23,30 @@ pub fn slugify(s: String) -> String {
     s.to_lowercase().split_whitespace().collect()
 }
 
+pub fn check_slug(slug: &str) -> Result<()> {
+    if !slug.is_empty() && slugify(slug.into()) == slug {
+        Ok(())
+    } else {
+        Err(CliError::Parse {
+            input: slug.into(),
+            description: "slugs should be lowercase without spaces".into(),
+        })
+    }
+}
+
+/// Whether missing values can be prompted for, i.e. stdin is a terminal.
+pub fn interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Error for a value that was not given as a flag and cannot be prompted for.
+pub fn missing_flag(flag: &str) -> CliError {
+    CliError::CmdError(format!(
+        "--{} is required when stdin is not a terminal",
+        flag
+    ))
+}
+
 pub fn parse_time(time_str: &str) -> Result<NaiveTime> {
     let fmt_time = format!("{}:00", time_str);
     match NaiveTime::from_str(&fmt_time) {
@@ -55,17 +81,27 @@ pub fn parse_date(date_str: &str) -> Result<NaiveDate> {
         match NaiveDate::parse_from_str(&input, "%Y-%m-%d") {
             Ok(date) => Ok(date),
             Err(_) => Err(CliError::Parse {
-                input: input,
+                input,
                 description: "should be in YYYY-MM-DD format".into(),
             }),
         }
     }
 }
 
+pub fn parse_month(month_str: &str) -> Result<NaiveDate> {
+    match NaiveDate::parse_from_str(&format!("{}-01", month_str), "%Y-%m-%d") {
+        Ok(date) => Ok(date),
+        Err(_) => Err(CliError::Parse {
+            input: month_str.into(),
+            description: "should be in YYYY-MM format".into(),
+        }),
+    }
+}
+
 pub fn partition_directive(directive: &str) -> Result<(&str, &str)> {
     if let Some(pos) = directive.find("::") {
-        let (dir, arg) = directive.split_at(pos);
-        if dir == "" || arg == "" {
+        let (dir, arg) = (&directive[..pos], &directive[pos + 2..]);
+        if dir.is_empty() || arg.is_empty() {
             Err(UtilError::DirectiveInvalid(directive.to_string()).into())
         } else {
             Ok((dir, arg))
@@ -76,6 +112,69 @@ pub fn partition_directive(directive: &str) -> Result<(&str, &str)> {
     }
 }
 
+/// Number of single character insertions, deletions and substitutions between `a` and `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Candidates resembling `input`, best first: those starting with it, then those within a
+/// few edits of it.
+pub fn suggest<'a>(input: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let input = input.to_lowercase();
+    let max_distance = (input.chars().count() / 3).max(2);
+    let mut ranked = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let prefix = candidate.starts_with(&input);
+            let distance = edit_distance(&input, candidate);
+            if prefix || distance <= max_distance {
+                Some((!prefix, distance, candidate))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<(bool, usize, &str)>>();
+    ranked.sort();
+    ranked
+        .into_iter()
+        .take(5)
+        .map(|(_, _, c)| c.to_string())
+        .collect()
+}
+
+/// " (did you mean a | b?)", or nothing when there are no suggestions.
+pub fn did_you_mean(suggestions: &[String]) -> String {
+    match suggestions {
+        [] => String::new(),
+        _ => format!(" (did you mean {}?)", suggestions.join(" | ").green()),
+    }
+}
+
+/// Error for an unknown filter field or sort key, suggesting the `known` ones.
+pub fn unknown_key(message: &str, key: &str, known: &[&str]) -> CliError {
+    let suggestions = match suggest(key, known.iter().copied()) {
+        s if s.is_empty() => known.iter().map(|k| k.to_string()).collect(),
+        s => s,
+    };
+    CliError::CmdError(format!(
+        "{} {}{}",
+        message,
+        key.yellow().bold(),
+        did_you_mean(&suggestions)
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,6 +185,15 @@ mod tests {
         assert_eq!(slugify("Upper spaced".into()), String::from("upperspaced"))
     }
 
+    #[test]
+    fn suggestions_rank_prefixes_before_typos() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        let slugs = vec!["website", "web", "mobile", "wbe", "backend"];
+        assert_eq!(suggest("web", slugs.clone()), vec!["web", "website", "wbe"]);
+        assert_eq!(suggest("mobil", slugs.clone()), vec!["mobile"]);
+        assert!(suggest("zzzzzz", slugs).is_empty());
+    }
+
     proptest! {
         #[test]
         fn can_parse_valid_date_pattern(y in 1i32..10000, m in 1u32..13, d in 1u32..28) {